@@ -1,7 +1,6 @@
 use bevy::prelude::*;
 use bevy_flycam::{FlyCam, NoCameraPlayerPlugin};
 use bevy_vox_mesh::VoxMeshPlugin;
-use std::f32::consts::PI;
 
 fn main() {
     App::default()
@@ -37,16 +36,14 @@ fn setup(
     });
 
     commands.spawn(PbrBundle {
-        transform: Transform::from_scale((0.01, 0.01, 0.01).into())
-            * Transform::from_rotation(Quat::from_axis_angle(Vec3::Y, PI)),
+        transform: Transform::from_scale((0.01, 0.01, 0.01).into()),
         mesh: assets.load("roads.vox#model0"),
         material: assets.load("roads.vox#material"),
         ..Default::default()
     });
 
     commands.spawn(PbrBundle {
-        transform: Transform::from_scale((0.01, 0.01, 0.01).into())
-            * Transform::from_rotation(Quat::from_axis_angle(Vec3::Y, PI)),
+        transform: Transform::from_scale((0.01, 0.01, 0.01).into()),
         mesh: assets.load("chicken.vox#model0"),
         material: assets.load("chicken.vox#material"),
         ..Default::default()
@@ -0,0 +1,162 @@
+use bevy::{
+    math::{UVec3, Vec3},
+    reflect::TypeUuid,
+    render::mesh::{Mesh, VertexAttributeValues},
+};
+
+use crate::{ColliderMode, VoxModel};
+
+/// Collision geometry generated from the voxels of a model of a `.vox` file, labeled `#collider{no}`.
+///
+/// The geometry is in the space of the meshes of the model, so that it can be attached to the spawned entities as is.
+/// With the `rapier` feature, it can be turned into a `bevy_rapier3d` collider with [`VoxCollider::to_rapier`].
+#[derive(Clone, Debug, TypeUuid)]
+#[uuid = "2f7c4b0e-91d3-4c6a-b8e5-7a0d3f1e6c58"]
+pub enum VoxCollider {
+    /// Boxes covering the solid voxels of the model.
+    Boxes(Vec<ColliderBox>),
+    /// The triangles of the mesh of the model.
+    TriMesh {
+        vertices: Vec<Vec3>,
+        indices: Vec<[u32; 3]>,
+    },
+}
+
+/// An axis aligned box of a [`VoxCollider`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ColliderBox {
+    pub center: Vec3,
+    pub half_extents: Vec3,
+}
+
+impl VoxCollider {
+    /// Generates the collision geometry of a model, reusing its mesh for [`ColliderMode::TriMesh`].
+    pub(crate) fn new(model: &VoxModel, mesh: &Mesh, mode: ColliderMode) -> Self {
+        match mode {
+            ColliderMode::Boxes => VoxCollider::Boxes(merge_boxes(model, true)),
+            ColliderMode::LayerBoxes => VoxCollider::Boxes(merge_boxes(model, false)),
+            ColliderMode::TriMesh => trimesh(mesh),
+        }
+    }
+
+    /// Creates a `bevy_rapier3d` collider with the same geometry.
+    #[cfg(feature = "rapier")]
+    pub fn to_rapier(&self) -> bevy_rapier3d::prelude::Collider {
+        use bevy::math::Quat;
+        use bevy_rapier3d::prelude::Collider;
+
+        match self {
+            VoxCollider::Boxes(boxes) => Collider::compound(
+                boxes
+                    .iter()
+                    .map(|collider_box| {
+                        let half_extents = collider_box.half_extents;
+                        (
+                            collider_box.center,
+                            Quat::IDENTITY,
+                            Collider::cuboid(half_extents.x, half_extents.y, half_extents.z),
+                        )
+                    })
+                    .collect(),
+            ),
+            VoxCollider::TriMesh { vertices, indices } => {
+                Collider::trimesh(vertices.clone(), indices.clone())
+            }
+        }
+    }
+}
+
+/// Greedily merges the solid voxels of a model into boxes, or into boxes a single voxel high if `vertical` isn't set.
+fn merge_boxes(model: &VoxModel, vertical: bool) -> Vec<ColliderBox> {
+    let size = model.size();
+    let index =
+        |position: UVec3| (position.x + size.x * (position.y + size.y * position.z)) as usize;
+    let mut merged = vec![false; (size.x * size.y * size.z) as usize];
+    let free = |merged: &[bool], position: UVec3| {
+        !merged[index(position)] && model.get(position.x, position.y, position.z).is_some()
+    };
+
+    // the horizontal axes, in the order the boxes grow along them.
+    let up = model.axis_convention().up();
+    let [first, second] = match up {
+        0 => [1, 2],
+        1 => [0, 2],
+        _ => [0, 1],
+    };
+    let at = |height: u32, b: u32, a: u32| {
+        let mut position = UVec3::ZERO;
+        position[up] = height;
+        position[second] = b;
+        position[first] = a;
+        position
+    };
+
+    let mut boxes = Vec::new();
+    for height in 0..size[up] {
+        for b in 0..size[second] {
+            for a in 0..size[first] {
+                let min = at(height, b, a);
+                if !free(&merged, min) {
+                    continue;
+                }
+
+                // grows the box along the first horizontal axis, then the second one, then up, as long as the added voxels are all solid.
+                let mut max = min;
+                while max[first] + 1 < size[first] && free(&merged, at(height, b, max[first] + 1)) {
+                    max[first] += 1;
+                }
+                while max[second] + 1 < size[second]
+                    && (min[first]..=max[first])
+                        .all(|a| free(&merged, at(height, max[second] + 1, a)))
+                {
+                    max[second] += 1;
+                }
+                while vertical
+                    && max[up] + 1 < size[up]
+                    && (min[second]..=max[second]).all(|b| {
+                        (min[first]..=max[first]).all(|a| free(&merged, at(max[up] + 1, b, a)))
+                    })
+                {
+                    max[up] += 1;
+                }
+
+                for height in min[up]..=max[up] {
+                    for b in min[second]..=max[second] {
+                        for a in min[first]..=max[first] {
+                            merged[index(at(height, b, a))] = true;
+                        }
+                    }
+                }
+
+                boxes.push(ColliderBox {
+                    center: (model.voxel_center(min) + model.voxel_center(max)) / 2.0,
+                    half_extents: (max - min + 1).as_vec3() * model.voxel_size() / 2.0,
+                });
+            }
+        }
+    }
+
+    boxes
+}
+
+/// Copies the triangles of a mesh.
+fn trimesh(mesh: &Mesh) -> VoxCollider {
+    let vertices = match mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+        Some(VertexAttributeValues::Float32x3(positions)) => {
+            positions.iter().copied().map(Vec3::from).collect()
+        }
+        _ => Vec::new(),
+    };
+    let indices: Vec<u32> = mesh
+        .indices()
+        .map(|indices| indices.iter().map(|index| index as u32).collect())
+        .unwrap_or_default();
+
+    VoxCollider::TriMesh {
+        vertices,
+        indices: indices
+            .chunks_exact(3)
+            .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+            .collect(),
+    }
+}
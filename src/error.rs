@@ -0,0 +1,47 @@
+use thiserror::Error;
+
+/// The largest model size along any axis MagicaVoxel can produce.
+pub(crate) const MAX_MODEL_SIZE: u32 = 256;
+
+/// An error occurring while loading a `.vox` file.
+///
+/// This is the error returned by the asset loader registered by [`crate::VoxMeshPlugin`], and can be retrieved by downcasting the [`anyhow::Error`] it is wrapped into.
+#[derive(Debug, Error)]
+pub enum VoxLoadError {
+    /// The file couldn't be parsed as a `.vox` file.
+    #[error("failed to parse .vox file: {0}")]
+    Parse(String),
+    /// The `.vox.meta` settings file next to the file couldn't be read or parsed.
+    #[error("failed to read loader settings: {0}")]
+    Settings(String),
+    /// The file has a material list which doesn't match its palette.
+    ///
+    /// This is only an error if the loader isn't configured to tolerate it, in which case default materials are used for the missing entries.
+    #[error("expected a material for every palette color, found {materials} materials for {palette} colors")]
+    InconsistentMaterials { palette: usize, materials: usize },
+    /// A shape node of the scene graph references a model which doesn't exist in the file.
+    #[error(
+        "scene node {node} references model {model_id}, but the file only contains {models} models"
+    )]
+    InvalidModelId {
+        node: usize,
+        model_id: u32,
+        models: usize,
+    },
+    /// A model is bigger than what MagicaVoxel supports.
+    #[error(
+        "model {model} has a size of {size:?}, which exceeds the maximum size of {}",
+        MAX_MODEL_SIZE
+    )]
+    ModelTooLarge { model: usize, size: [u32; 3] },
+    /// A voxel of a model lies outside of the size of the model.
+    #[error("model {model} has a voxel at {position:?}, outside of its size of {size:?}")]
+    VoxelOutOfBounds {
+        model: usize,
+        position: [u8; 3],
+        size: [u32; 3],
+    },
+    /// A frame of a transform node of the scene graph has a `_r` attribute which isn't a packed rotation.
+    #[error("scene node {node} has an invalid rotation {rotation:?}")]
+    InvalidRotation { node: usize, rotation: String },
+}
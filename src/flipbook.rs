@@ -0,0 +1,108 @@
+use bevy::{prelude::*, render::primitives::Aabb};
+
+/// Cycles the mesh of an entity through the frames of a frame by frame animation.
+///
+/// The scene spawned for a `.vox` file inserts this component on the entity of each material of shapes with several models, playing in a loop.
+/// A [`crate::VoxPaletteSwap`] recolors every frame.
+#[derive(Component, Reflect, Clone, Debug, Default)]
+#[reflect(Component)]
+pub struct VoxFlipbook {
+    /// The mesh of each frame.
+    pub frames: Vec<Handle<Mesh>>,
+    /// The translation of the entity during each frame, so that meshes with different origins stay aligned,
+    /// or nothing to leave the transform of the entity alone.
+    pub translations: Vec<Vec3>,
+    /// The number of frames per second.
+    pub frame_rate: f32,
+    /// Whether the animation is playing.
+    pub playing: bool,
+    /// Whether the animation starts over after its last frame, instead of stopping on it.
+    pub looping: bool,
+    elapsed: f32,
+}
+
+impl VoxFlipbook {
+    /// Creates a [`VoxFlipbook`] playing the given frames in a loop.
+    pub fn new(frames: Vec<Handle<Mesh>>, frame_rate: f32) -> Self {
+        Self {
+            frames,
+            translations: Vec::new(),
+            frame_rate,
+            playing: true,
+            looping: true,
+            elapsed: 0.0,
+        }
+    }
+
+    /// Moves the entity to the given translation during each frame.
+    pub fn with_translations(mut self, translations: Vec<Vec3>) -> Self {
+        self.translations = translations;
+        self
+    }
+
+    /// Resumes the animation.
+    pub fn play(&mut self) {
+        self.playing = true;
+    }
+
+    /// Pauses the animation on the current frame.
+    pub fn pause(&mut self) {
+        self.playing = false;
+    }
+
+    /// Sets whether the animation starts over after its last frame.
+    pub fn set_looping(&mut self, looping: bool) {
+        self.looping = looping;
+    }
+
+    /// Jumps to the given frame.
+    pub fn set_frame(&mut self, frame: usize) {
+        self.elapsed = frame as f32 / self.frame_rate;
+    }
+
+    /// Returns the index of the current frame.
+    pub fn frame(&self) -> usize {
+        let frame = (self.elapsed * self.frame_rate) as usize;
+        match self.looping {
+            true => frame % self.frames.len().max(1),
+            false => frame.min(self.frames.len().saturating_sub(1)),
+        }
+    }
+}
+
+pub(crate) fn animate_flipbooks(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut flipbooks: Query<(Entity, &mut VoxFlipbook, &mut Handle<Mesh>, &mut Transform)>,
+) {
+    for (entity, mut flipbook, mut mesh, mut transform) in flipbooks.iter_mut() {
+        if flipbook.frames.is_empty() || flipbook.frame_rate <= 0.0 {
+            continue;
+        }
+
+        if flipbook.playing {
+            flipbook.elapsed += time.delta_seconds();
+            // a flipbook which doesn't loop stops on its last frame.
+            let duration = flipbook.frames.len() as f32 / flipbook.frame_rate;
+            if !flipbook.looping && flipbook.elapsed >= duration {
+                flipbook.playing = false;
+            } else if flipbook.looping {
+                flipbook.elapsed %= duration;
+            }
+        }
+
+        let frame = flipbook.frame();
+        if let Some(frame_mesh) = flipbook.frames.get(frame) {
+            if *mesh != *frame_mesh {
+                *mesh = frame_mesh.clone();
+                // bevy keeps the bounding box of the first frame otherwise, culling frames which reach past it.
+                commands.entity(entity).remove::<Aabb>();
+            }
+        }
+        if let Some(translation) = flipbook.translations.get(frame) {
+            if transform.translation != *translation {
+                transform.translation = *translation;
+            }
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::utils::HashMap;
+
+/// Marks the root entity of the scene spawned for a `.vox` file.
+///
+/// A [`VoxSceneIndex`] is inserted next to it once the scene is spawned.
+#[derive(Component, Reflect, Clone, Copy, Debug, Default)]
+#[reflect(Component)]
+pub struct VoxSceneRoot;
+
+/// Finds the entities of a spawned `.vox` scene by name.
+///
+/// Inserted on the [`VoxSceneRoot`] entity once the scene is spawned.
+#[derive(Component, Clone, Debug, Default)]
+pub struct VoxSceneIndex {
+    names: HashMap<String, Entity>,
+    paths: HashMap<String, Entity>,
+}
+
+impl VoxSceneIndex {
+    /// Returns the entity with the given [`Name`], such as the `_name` of a MagicaVoxel node.
+    ///
+    /// Names are only unique among siblings, and the entities of the meshes of different models share the same names,
+    /// so the first entity found is returned; use [`VoxSceneIndex::get_path`] to find the others.
+    pub fn get(&self, name: &str) -> Option<Entity> {
+        self.names.get(name).copied()
+    }
+
+    /// Returns the entity at the given path of [`Name`]s separated by `/`, relative to the root,
+    /// e.g. `door/handle` or `door/handle/opaque` for the entity of the opaque mesh of the `handle` node.
+    pub fn get_path(&self, path: &str) -> Option<Entity> {
+        self.paths.get(path).copied()
+    }
+
+    /// Iterates over the paths of the named entities of the scene, and their entity.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, Entity)> {
+        self.paths
+            .iter()
+            .map(|(path, entity)| (path.as_str(), *entity))
+    }
+
+    fn insert(&mut self, entity: Entity, parent: &str, name: &str) -> String {
+        let path = match parent {
+            "" => name.to_owned(),
+            _ => format!("{parent}/{name}"),
+        };
+        self.names.entry(name.to_owned()).or_insert(entity);
+        self.paths.insert(path.clone(), entity);
+        path
+    }
+}
+
+/// The models of the shapes of a `.vox` file, labeled `#instances`.
+///
+/// Shapes are found by the path of the entity spawned for their transform node, as used by [`VoxSceneIndex::get_path`],
+/// and map to the index of their model, whose mesh is labeled `#model{no}`.
+#[derive(Clone, Debug, Default, TypeUuid)]
+#[uuid = "0d3e7a51-4c2b-4f8e-9a6d-1b5c8e2f7a93"]
+pub struct VoxInstances {
+    models: HashMap<String, usize>,
+}
+
+impl VoxInstances {
+    pub(crate) fn new(models: HashMap<String, usize>) -> Self {
+        Self { models }
+    }
+
+    /// Returns the index of the model of the shape at the given path, e.g. `turret2/barrel`.
+    ///
+    /// Shapes with several models, spawned as a [`crate::VoxFlipbook`], return their first model.
+    pub fn model(&self, path: &str) -> Option<usize> {
+        self.models.get(path).copied()
+    }
+
+    /// Iterates over the paths of the shapes, and the index of their model.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, usize)> {
+        self.models
+            .iter()
+            .map(|(path, model)| (path.as_str(), *model))
+    }
+}
+
+/// A named shape of a `.vox` file, labeled `#instance-{name}` after the `_name` of its transform node,
+/// suffixed with `-{node}` if an earlier node has the same name.
+///
+/// Every instance of a model shares its mesh, centred on the pivot of the model, and is only placed by the scene.
+#[derive(Clone, Debug, TypeUuid)]
+#[uuid = "f66b7f2c-63ec-46d7-bced-563aae651d01"]
+pub struct VoxInstance {
+    /// The index of the model of the shape, or of its first model if it has several.
+    pub model: usize,
+    /// The mesh of the model, labeled `#model{no}`.
+    pub mesh: Handle<Mesh>,
+    /// The path of the entity spawned for the transform node, as used by [`VoxSceneIndex::get_path`],
+    /// or `None` if the node isn't spawned, such as in a skipped hidden layer.
+    pub path: Option<String>,
+}
+
+/// Builds the [`VoxSceneIndex`] of the scenes spawned since the last run.
+pub(crate) fn index_scenes(
+    mut commands: Commands,
+    roots: Query<Entity, Added<VoxSceneRoot>>,
+    children: Query<&Children>,
+    names: Query<&Name>,
+) {
+    for root in roots.iter() {
+        let mut index = VoxSceneIndex::default();
+        let mut stack: Vec<(Entity, String)> = vec![(root, String::new())];
+        while let Some((entity, path)) = stack.pop() {
+            for child in children.get(entity).into_iter().flatten() {
+                let path = match names.get(*child) {
+                    Ok(name) => index.insert(*child, &path, name.as_str()),
+                    Err(_) => path.clone(),
+                };
+                stack.push((*child, path));
+            }
+        }
+        commands.entity(root).insert(index);
+    }
+}
@@ -0,0 +1,47 @@
+use bevy::prelude::*;
+use dot_vox::{Dict, Layer};
+
+// constants used in magicavoxel's layer dictionaries
+const LAYER: &str = "_layer";
+const NAME: &str = "_name";
+const HIDDEN: &str = "_hidden";
+
+/// The MagicaVoxel layer of a spawned shape.
+///
+/// The scene spawned for a `.vox` file inserts this component on each shape and on the entities of its meshes,
+/// so that the shapes of a layer can be queried, e.g. to turn the `collision` layer into colliders.
+#[derive(Component, Reflect, Clone, Debug, Default, PartialEq, Eq)]
+#[reflect(Component)]
+pub struct VoxLayer {
+    /// The index of the layer in the file.
+    pub id: u32,
+    /// The name of the layer, empty if it wasn't named.
+    pub name: String,
+}
+
+impl VoxLayer {
+    /// Returns the layer of the given index, and whether it is hidden.
+    pub(crate) fn new(layers: &[Layer], id: u32) -> (Self, bool) {
+        let attributes = layers.get(id as usize).map(|layer| &layer.attributes);
+        let layer = Self {
+            id,
+            name: attributes
+                .and_then(|attributes| attributes.get(NAME))
+                .cloned()
+                .unwrap_or_default(),
+        };
+        let hidden = attributes
+            .and_then(|attributes| attributes.get(HIDDEN))
+            .map_or(false, |hidden| hidden == "1");
+        (layer, hidden)
+    }
+}
+
+/// Returns the layer referenced by the attributes of a transform node, if any.
+pub(crate) fn extract_layer(attributes: &Dict) -> Option<u32> {
+    attributes
+        .get(LAYER)
+        .and_then(|layer| layer.parse::<i32>().ok())
+        // MagicaVoxel uses -1 for nodes which aren't in a layer, such as the root group
+        .and_then(|layer| u32::try_from(layer).ok())
+}
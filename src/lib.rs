@@ -18,61 +18,269 @@
 //!   let second_mesh = asset_loader.load("my_voxel_model.vox#model1");
 //!}
 //!```
+//!
+//! # Asset labels
+//!
+//! The meshes generated from `.vox` files use the standard [`bevy::render::mesh::Mesh`] attributes for easier compatibility with shaders,
+//! along with the palette index and ambient occlusion of each vertex ([`crate::ATTRIBUTE_PALETTE_INDEX`], [`crate::ATTRIBUTE_OCCLUSION`]).
+//! To get the `StandardMaterial` associated with a `.vox` file, append `#material` to the asset loading path.
+//! You can load multiple models from the same `.vox` file by appending `#model{no}` to the asset loading path, where `{no}` corresponds to the model index in the file.
+//! The meshes are centred on the pivot of their model. Each named transform node of a shape is a [`crate::VoxInstance`]
+//! labeled `#instance-{name}`, suffixed with `-{node}` if an earlier node has the same name, which holds the shared mesh of its model.
+//! These replace the `#model-{name}` meshes, which were copies placed like one of the instances of the model.
+//! The model of each shape of the scene is also found by its path in the [`crate::VoxInstances`] labeled `#instances`.
+//! Models spawned under a mirrored transform node, which the scene spawns with a negative scale, also get copies of their meshes
+//! with reversed winding labeled `#model{no}/mirrored`, `#model{no}/mirrored/{material}` and so on, which are updated by edits
+//! along with the meshes they're copied from, except for the less detailed meshes.
+//!
+//! Each model is also split into one sub-mesh per material used by its voxels, labeled `#model{no}/{material}`,
+//! to be rendered with the material labeled `#material/{material}` (or `#material` for the default `opaque` material).
+//! Palette entries with the default diffuse material use the `opaque` material, and the `transparent` material takes the properties
+//! of the first `_glass` entry. Other entries are grouped by their physically based properties (`_metal`, `_rough`, `_spec`, `_ior`, `_emit`, `_flux`)
+//! into materials named `opaque{index}`, `transparent{index}` or `emissive{index}`, where `{index}` is the first palette index using it,
+//! so glass entries only get a `transparent{index}` material when they differ from the first one.
+//! Splitting the sub-meshes keeps `_glass` voxels from forcing the whole model to be blended.
+//! Every material slot gets a sub-mesh, which is empty if the model has no voxels using it, so that voxels added by edits show up.
+//!
+//! The palette of the file is available as a texture with one texel per color by appending `#palette` to the asset loading path.
+//! With [`crate::ColorMode::PaletteTexture`], the meshes use UVs pointing into this texture instead of vertex colors, and the materials sample it.
+//! The palette colors themselves are available as a [`crate::VoxPalette`] by appending `#palette/colors`, to be used with a [`crate::VoxPaletteSwap`].
+//!
+//! If the models are split into chunks with [`crate::VoxLoaderSettings::chunk_size`], the sub-mesh of each chunk and material is labeled
+//! `#model{no}/chunk{chunk}/{material}`, and the spawned scene uses these sub-meshes.
+//! Only the chunks and materials with voxels have a sub-mesh, the others are added and spawned once edits put voxels in them.
+//!
+//! If [`crate::VoxLoaderSettings::lod_levels`] is set, the less detailed meshes of each model are labeled `#model{no}/lod{level}`,
+//! and their sub-meshes `#model{no}/lod{level}/{material}`.
+//!
+//! The voxels of each model are available as a [`crate::VoxModel`] by appending `#voxels{no}` to the asset loading path,
+//! and if [`crate::VoxLoaderSettings::collider`] is set, its collision geometry as a [`crate::VoxCollider`] by appending `#collider{no}`.
+//!
+//! The default asset of a `.vox` file is a [`bevy::scene::Scene`] mirroring its scene graph: each transform node is spawned
+//! with its local transform and a [`bevy::prelude::Name`] from its `_name` (suffixed with `#{node}` if a sibling has the same name),
+//! as the parent of the children of its group or of the meshes of its shape, so that moving a group moves all of its parts.
+//! The entities of the meshes are named after their material, prefixed with their chunk if the model is split into chunks.
+//! The root entity of the scene has a [`crate::VoxSceneRoot`], next to which a [`crate::VoxSceneIndex`] is inserted once spawned.
+//! If transform nodes have several keyframes, the scene is animated by an [`bevy::animation::AnimationPlayer`]
+//! playing the [`bevy::animation::AnimationClip`] labeled `#animation`, timed with [`crate::VoxLoaderSettings::frame_rate`].
+//! Shapes with several models are spawned with an entity per material, cycling through the `#model{no}/{material}` sub-mesh
+//! of each model with a [`crate::VoxFlipbook`], and moved so that each model stays on its pivot.
+//! The shapes in a MagicaVoxel layer, and the entities of their meshes, have a [`crate::VoxLayer`] with its index and name,
+//! and the shapes of hidden layers are hidden or skipped according to [`crate::VoxLoaderSettings::hidden_layers`].
+//!
+//! The [`crate::VoxLoaderSettings`] used for a file are the plugin defaults, overridden by the `.vox.meta` file next to it if there is one.
 
 use bevy::{
+    animation::AnimationClip,
     app::{App, Plugin},
     prelude::AddAsset,
 };
-use block_mesh::{QuadCoordinateConfig, RIGHT_HANDED_Y_UP_CONFIG};
+use block_mesh::QuadCoordinateConfig;
+
+mod collider;
+pub use collider::{ColliderBox, VoxCollider};
+
+mod error;
+pub use error::VoxLoadError;
 
 mod loader;
 #[doc(inline)]
 use loader::VoxLoader;
 
+mod settings;
+pub use settings::{
+    AxisConvention, ColliderMode, ColorMode, HiddenLayers, MeshingAlgorithm, PivotMode,
+    VoxLoaderSettings,
+};
+
+mod flipbook;
+pub use flipbook::VoxFlipbook;
+
+mod index;
+pub use index::{VoxInstance, VoxInstances, VoxSceneIndex, VoxSceneRoot};
+
+mod layer;
+pub use layer::VoxLayer;
+
+mod lod;
+pub use lod::VoxLod;
+
 mod material;
 mod mesh;
+pub use mesh::{ATTRIBUTE_OCCLUSION, ATTRIBUTE_PALETTE_INDEX};
+
+mod model;
+pub use model::{VoxModel, VoxelEdit};
+
+mod occlusion;
+
+mod palette;
+pub use palette::{VoxPalette, VoxPaletteSwap};
+
+mod raycast;
+pub use raycast::VoxelHit;
+
 mod scene;
 mod voxel;
 
+#[cfg(test)]
+mod tests;
+
 /// The core plugin adding functionality for loading `.vox` files.
 ///
 /// Registers an [`bevy::asset::AssetLoader`] capable of loading modes in `.vox` files as usable [`bevy::render::mesh::Mesh`].
 pub struct VoxMeshPlugin {
-    config: QuadCoordinateConfig,
-    v_flip_faces: bool,
-    convert_rgb_to_linear: bool,
+    settings: VoxLoaderSettings,
 }
 
 impl VoxMeshPlugin {
     /// Creates a [`VoxMeshPlugin`] instance with the specified parameters
     ///
     /// # Arguments
-    /// * `config` - The quad coordinates configuration ([`QuadCoordinateConfig`]) to use when meshing models.
+    /// * `config` - Ignored, the quad coordinates configuration used when meshing models is derived from [`VoxLoaderSettings::axis_convention`].
+    #[deprecated(
+        note = "the quad coordinates configuration is derived from the axis convention, use `VoxMeshPlugin::default().with_settings(..)` instead"
+    )]
     pub fn with_options(
-        config: QuadCoordinateConfig,
+        _config: QuadCoordinateConfig,
         v_flip_faces: bool,
         convert_rgb_to_linear: bool,
     ) -> Self {
         Self {
-            config,
-            v_flip_faces,
-            convert_rgb_to_linear,
+            settings: VoxLoaderSettings {
+                v_flip_faces,
+                convert_rgb_to_linear,
+                ..VoxLoaderSettings::default()
+            },
         }
     }
+
+    /// Sets the [`VoxLoaderSettings`] used for the `.vox` files which don't override them with a `.vox.meta` file.
+    pub fn with_settings(mut self, settings: VoxLoaderSettings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    /// Sets the size of a voxel in world units, so that meshes and scenes don't need to be scaled down when spawned.
+    ///
+    /// # Panics
+    /// Panics if `voxel_size` isn't a positive number.
+    pub fn with_voxel_size(mut self, voxel_size: f32) -> Self {
+        assert!(
+            voxel_size.is_finite() && voxel_size > 0.0,
+            "voxel_size must be a positive number, got {voxel_size}"
+        );
+        self.settings.voxel_size = voxel_size;
+        self
+    }
+
+    /// Sets where the origin of the generated meshes is placed.
+    pub fn with_pivot(mut self, pivot: PivotMode) -> Self {
+        self.settings.pivot = pivot;
+        self
+    }
+
+    /// Sets whether per-vertex ambient occlusion is baked into the generated meshes.
+    pub fn with_ambient_occlusion(mut self, ambient_occlusion: bool) -> Self {
+        self.settings.ambient_occlusion = ambient_occlusion;
+        self
+    }
+
+    /// Sets the algorithm used to turn the voxels into meshes.
+    pub fn with_meshing(mut self, meshing: MeshingAlgorithm) -> Self {
+        self.settings.meshing = meshing;
+        self
+    }
+
+    /// Sets how the palette colors are applied to the generated meshes.
+    pub fn with_color_mode(mut self, color_mode: ColorMode) -> Self {
+        self.settings.color_mode = color_mode;
+        self
+    }
+
+    /// Splits the models into chunks of the given size in voxels, spawned as separate entities.
+    /// A chunk size of `0` disables the splitting, as in a `.vox.meta` file.
+    pub fn with_chunk_size(mut self, chunk_size: u32) -> Self {
+        self.settings.chunk_size = (chunk_size > 0).then_some(chunk_size);
+        self
+    }
+
+    /// Generates the given number of less detailed meshes for each model,
+    /// the first one being used from the given distance to the nearest camera.
+    pub fn with_lods(mut self, levels: u32, distance: f32) -> Self {
+        self.settings.lod_levels = levels;
+        self.settings.lod_distance = distance;
+        self
+    }
+
+    /// Generates collision geometry for each model.
+    pub fn with_collider(mut self, collider: ColliderMode) -> Self {
+        self.settings.collider = Some(collider);
+        self
+    }
+
+    /// Sets the number of animation frames per second of the animations of the scenes.
+    ///
+    /// # Panics
+    /// Panics if `frame_rate` isn't a positive number.
+    pub fn with_frame_rate(mut self, frame_rate: f32) -> Self {
+        assert!(
+            frame_rate.is_finite() && frame_rate > 0.0,
+            "frame_rate must be a positive number, got {frame_rate}"
+        );
+        self.settings.frame_rate = frame_rate;
+        self
+    }
+
+    /// Sets what happens to the shapes of the layers hidden in MagicaVoxel when the scene is spawned.
+    pub fn with_hidden_layers(mut self, hidden_layers: HiddenLayers) -> Self {
+        self.settings.hidden_layers = hidden_layers;
+        self
+    }
+
+    /// Sets the axes of the generated meshes, voxel grids and scenes.
+    pub fn with_axis_convention(mut self, axis_convention: AxisConvention) -> Self {
+        self.settings.axis_convention = axis_convention;
+        self
+    }
+
+    /// Sets whether files with a material list which doesn't match their palette are loaded with default materials for the missing entries,
+    /// instead of failing with [`VoxLoadError::InconsistentMaterials`].
+    pub fn with_tolerant_materials(mut self, tolerant_materials: bool) -> Self {
+        self.settings.tolerant_materials = tolerant_materials;
+        self
+    }
 }
 
 impl Default for VoxMeshPlugin {
     fn default() -> Self {
-        Self::with_options(RIGHT_HANDED_Y_UP_CONFIG, true, false)
+        Self {
+            settings: VoxLoaderSettings::default(),
+        }
     }
 }
 
 impl Plugin for VoxMeshPlugin {
     fn build(&self, app: &mut App) {
-        app.add_asset_loader(VoxLoader {
-            config: self.config.clone(),
-            v_flip_face: self.v_flip_faces,
-            convert_rgb_to_linear: self.convert_rgb_to_linear,
-        });
+        app.add_asset::<VoxPalette>()
+            .add_asset::<VoxModel>()
+            .add_asset::<VoxCollider>()
+            .add_asset::<VoxInstance>()
+            .add_asset::<VoxInstances>()
+            .add_asset::<AnimationClip>()
+            .register_type::<VoxLod>()
+            .register_type::<VoxFlipbook>()
+            .register_type::<VoxLayer>()
+            .register_type::<VoxSceneRoot>()
+            .register_type::<scene::ChunkedShape>()
+            .add_asset_loader(VoxLoader {
+                settings: self.settings.clone(),
+            })
+            .add_system(palette::apply_palette_swaps)
+            .add_system(model::remesh_edited_models)
+            .add_system(model::reset_modified_bounds)
+            .add_system(lod::select_lods)
+            .add_system(flipbook::animate_flipbooks)
+            .add_system(index::index_scenes);
     }
 }
@@ -1,21 +1,25 @@
-use anyhow::{anyhow, Error};
+use std::{path::PathBuf, sync::Arc};
+
+use anyhow::Error;
 use bevy::{
-    asset::{AssetLoader, LoadContext, LoadedAsset},
-    prelude::Color,
+    asset::{AssetIoError, AssetLoader, AssetPath, Handle, LoadContext, LoadedAsset},
+    prelude::{Color, Mesh},
+};
+
+use crate::{
+    material::{MaterialSlot, PaletteMaterials},
+    mesh::{empty_mesh, reversed_winding, ModelMesher},
+    palette::palette_image,
+    scene::{SpawnedModel, SpawnedPart},
+    settings::{VoxLoaderSettings, VoxLoaderSettingsOverrides},
+    ColorMode, VoxCollider, VoxLoadError, VoxModel, VoxPalette,
 };
-use block_mesh::QuadCoordinateConfig;
 
 /// An asset loader capable of loading models in `.vox` files as usable [`bevy::render::mesh::Mesh`]es.
 ///
-/// The meshes generated by this asset loader only use standard [`bevy::render::mesh::Mesh`] attributes for easier compatibility with shaders.
-/// To get the `StandardMaterial` associated with a `.vox` file, append `#material` to the asset loading path.
-/// You can load multiple models from the same `.vox` file by appending `#model{no}` to the asset loading path, where `{no}` corresponds to the model index in the file.
+/// The default asset of a file is a [`bevy::scene::Scene`], and the other assets are labeled as described in the crate documentation.
 pub struct VoxLoader {
-    /// Whether to flip the UVs vertically when meshing the models.
-    /// You may want to change this to false if you aren't using Vulkan as a graphical backend for bevy , else this should default to true.
-    pub(crate) config: QuadCoordinateConfig,
-    pub(crate) v_flip_face: bool,
-    pub(crate) convert_rgb_to_linear: bool,
+    pub(crate) settings: VoxLoaderSettings,
 }
 
 impl AssetLoader for VoxLoader {
@@ -25,7 +29,8 @@ impl AssetLoader for VoxLoader {
         load_context: &'a mut LoadContext,
     ) -> bevy::utils::BoxedFuture<'a, Result<(), Error>> {
         Box::pin(async move {
-            self.process_vox_file(bytes, load_context)?;
+            let settings = self.read_settings(load_context).await?;
+            self.process_vox_file(bytes, load_context, &settings)?;
             Ok(())
         })
     }
@@ -36,23 +41,51 @@ impl AssetLoader for VoxLoader {
 }
 
 impl VoxLoader {
+    async fn read_settings<'a>(
+        &self,
+        load_context: &LoadContext<'a>,
+    ) -> Result<VoxLoaderSettings, VoxLoadError> {
+        let mut meta_path = load_context.path().as_os_str().to_owned();
+        meta_path.push(".meta");
+
+        let settings = match load_context
+            .read_asset_bytes(PathBuf::from(meta_path))
+            .await
+        {
+            Ok(bytes) => {
+                let overrides: VoxLoaderSettingsOverrides = ron::de::from_bytes(&bytes)
+                    .map_err(|error| VoxLoadError::Settings(error.to_string()))?;
+                overrides.apply(&self.settings)
+            }
+            Err(AssetIoError::NotFound(_)) => self.settings.clone(),
+            Err(error) => return Err(VoxLoadError::Settings(error.to_string())),
+        };
+        settings.validate().map_err(VoxLoadError::Settings)?;
+        Ok(settings)
+    }
+
     fn process_vox_file<'a>(
         &self,
         bytes: &'a [u8],
         load_context: &'a mut LoadContext,
-    ) -> Result<(), Error> {
+        settings: &VoxLoaderSettings,
+    ) -> Result<(), VoxLoadError> {
         let file = match dot_vox::load_bytes(bytes) {
             Ok(data) => data,
-            Err(error) => return Err(anyhow!(error)),
+            Err(error) => return Err(VoxLoadError::Parse(error.to_string())),
         };
 
-        let palette: Vec<[f32; 4]> = file
+        crate::model::check_models(&file.models)?;
+        crate::scene::check_model_ids(&file.scene, file.models.len())?;
+        crate::scene::check_rotations(&file.scene)?;
+
+        let mut palette: Vec<[f32; 4]> = file
             .palette
             .iter()
             .map(|color| {
                 let rgba = color.to_le_bytes().map(|byte| byte as f32 / u8::MAX as f32);
 
-                if self.convert_rgb_to_linear {
+                if settings.convert_rgb_to_linear {
                     Color::rgba(rgba[0], rgba[1], rgba[2], rgba[3]).as_linear_rgba_f32()
                 } else {
                     rgba
@@ -60,37 +93,247 @@ impl VoxLoader {
             })
             .collect();
 
-        let material = crate::material::load_material(load_context, &palette, &file.materials);
+        let palette_materials =
+            PaletteMaterials::new(&mut palette, &file.materials, settings.tolerant_materials)?;
+        let palette_colors = load_context.set_labeled_asset(
+            "palette/colors",
+            LoadedAsset::new(VoxPalette {
+                colors: palette.clone(),
+                linear: settings.convert_rgb_to_linear,
+            }),
+        );
+        let palette_texture = load_context.set_labeled_asset(
+            "palette",
+            LoadedAsset::new(palette_image(&palette, settings.convert_rgb_to_linear)),
+        );
+        let materials = palette_materials.load(
+            load_context,
+            (settings.color_mode == ColorMode::PaletteTexture).then_some(palette_texture),
+        );
+
+        let palette: Arc<[[f32; 4]]> = palette.into();
+        let palette_materials = Arc::new(palette_materials);
+        let quads_config = Arc::new(settings.axis_convention.quads_config());
 
+        let mirrored_models = crate::scene::extract_mirrored(&file.scene, file.models.len());
         let mut meshes = Vec::new();
         for (index, model) in file.models.iter().enumerate() {
-            let (shape, buffer) = crate::voxel::load_from_model(model);
-            let (name, translation) = crate::scene::extract_model_attributes(index, &file.scene);
-            let mesh = crate::mesh::mesh_model(
-                shape,
-                &buffer,
-                &palette,
-                &self.config,
-                self.v_flip_face,
-                translation,
+            let size = [model.size.x, model.size.y, model.size.z];
+            let origin = crate::mesh::mesh_origin(size, settings);
+            let mut voxels = VoxModel::from_model(
+                model,
+                origin,
+                settings.voxel_size,
+                palette_colors.clone(),
+                settings.axis_convention,
             );
+            let mut mesher = ModelMesher::new(
+                &voxels,
+                palette.clone(),
+                palette_materials.clone(),
+                quads_config.clone(),
+                settings,
+            );
+            let model_meshes = mesher.mesh_loaded(&voxels);
 
-            if let Some(name) = name {
-                meshes.push(
-                    load_context.set_labeled_asset(
-                        &format!("model-{name}"),
-                        LoadedAsset::new(mesh.clone()),
-                    ),
+            if let Some(collider) = settings.collider {
+                load_context.set_labeled_asset(
+                    &format!("collider{index}"),
+                    LoadedAsset::new(VoxCollider::new(&voxels, &model_meshes.mesh, collider)),
                 );
             }
 
-            meshes.push(
-                load_context.set_labeled_asset(&format!("model{index}"), LoadedAsset::new(mesh)),
-            );
+            let mirrored = mirrored_models[index];
+            let (mesh, mirrored_mesh) =
+                set_model_mesh(load_context, index, "", model_meshes.mesh, mirrored);
+            mesher.meshes.push(mesh);
+            mesher.mirrored_meshes.extend(mirrored_mesh.clone());
+
+            // one sub-mesh per material slot, so that only the voxels which need blending are sorted as transparent.
+            // slots without voxels get an empty sub-mesh, which the mesher fills in place once voxels use them.
+            let slots = &palette_materials.slots;
+            let parts = model_meshes
+                .parts
+                .into_iter()
+                .zip(slots.iter())
+                .map(|(part, slot_material)| {
+                    let suffix = format!("/{}", slot_material.label);
+                    let part = part.unwrap_or_else(empty_mesh);
+                    set_model_mesh(load_context, index, &suffix, part, mirrored)
+                })
+                .collect();
+            let (parts, mirrored_parts) = split_mirrored(parts);
+            mesher.parts = parts;
+            mesher.mirrored_parts = mirrored_parts.clone();
+
+            // chunk sub-meshes are labeled after their chunk index, then their slot.
+            // only the chunks and slots with voxels get a sub-mesh, the others are added once edits put voxels in them.
+            let (chunk_parts, mirrored_chunk_parts): (Vec<_>, Vec<_>) = model_meshes
+                .chunks
+                .into_iter()
+                .map(|(chunk, parts)| {
+                    parts
+                        .into_iter()
+                        .zip(slots.iter())
+                        .map(|(part, slot_material)| {
+                            let suffix = format!("/chunk{chunk}/{}", slot_material.label);
+                            match part {
+                                Some(part) => {
+                                    let (mesh, mirrored_part) = set_model_mesh(
+                                        load_context,
+                                        index,
+                                        &suffix,
+                                        part,
+                                        mirrored,
+                                    );
+                                    (Some(mesh), mirrored_part)
+                                }
+                                None => (None, None),
+                            }
+                        })
+                        .unzip::<_, _, Vec<_>, Vec<_>>()
+                })
+                .unzip();
+            mesher.chunk_parts = chunk_parts;
+            mesher.mirrored_chunk_parts = mirrored_chunk_parts;
+            mesher.slot_materials = materials.clone();
+
+            // less detailed sub-meshes are generated for every material slot, even if they end up empty.
+            let mut lods: Vec<Vec<Handle<Mesh>>> = vec![Vec::new(); mesher.parts.len()];
+            let mut mirrored_lods: Vec<Vec<Handle<Mesh>>> = vec![Vec::new(); mesher.parts.len()];
+            for level in 1..=settings.lod_levels {
+                let lod_meshes = crate::lod::mesh_lod(
+                    &voxels,
+                    level,
+                    palette.clone(),
+                    palette_materials.clone(),
+                    quads_config.clone(),
+                    settings,
+                );
+                load_context.set_labeled_asset(
+                    &format!("model{index}/lod{level}"),
+                    LoadedAsset::new(lod_meshes.mesh),
+                );
+                for (slot, part) in lod_meshes.parts.into_iter().enumerate() {
+                    let suffix = format!("/lod{level}/{}", slots[slot].label);
+                    let part = part.unwrap_or_else(empty_mesh);
+                    let (lod, mirrored_lod) =
+                        set_model_mesh(load_context, index, &suffix, part, mirrored);
+                    lods[slot].push(lod);
+                    mirrored_lods[slot].extend(mirrored_lod);
+                }
+            }
+
+            // the scene refers to the voxels of split models to spawn the chunks which edits put voxels in.
+            let voxels_label = format!("voxels{index}");
+            let voxels_handle: Handle<VoxModel> = load_context.get_handle(AssetPath::new_ref(
+                load_context.path(),
+                Some(voxels_label.as_str()),
+            ));
+            let chunks = mesher
+                .is_split()
+                .then(|| chunk_parts(slots, &mesher.chunk_parts));
+            let mirrored_chunks = mesher
+                .is_split()
+                .then(|| chunk_parts(slots, &mesher.mirrored_chunk_parts));
+            meshes.push(SpawnedModel {
+                mesh: mesher.meshes.last().cloned().unwrap_or_default(),
+                voxels: voxels_handle.clone(),
+                slots: slot_parts(slots, &mesher.parts, lods),
+                chunks,
+                mirrored: mirrored_mesh.map(|mesh| {
+                    Box::new(SpawnedModel {
+                        mesh,
+                        voxels: voxels_handle,
+                        slots: slot_parts(slots, &mirrored_parts, mirrored_lods),
+                        chunks: mirrored_chunks,
+                        mirrored: None,
+                    })
+                }),
+            });
+
+            voxels.mesher = Some(Box::new(mesher));
+            load_context.set_labeled_asset(&voxels_label, LoadedAsset::new(voxels));
         }
 
-        crate::scene::load_scene(load_context, material, &file.models, &meshes, &file.scene);
+        crate::scene::load_scene(
+            load_context,
+            &materials,
+            &file.models,
+            &meshes,
+            &file.scene,
+            &file.layers,
+            settings,
+        );
 
         Ok(())
     }
 }
+
+/// Sets a mesh of a model as the asset labeled `model{index}{suffix}`,
+/// along with a copy with reversed winding labeled `model{index}/mirrored{suffix}` if the model is spawned mirrored.
+fn set_model_mesh(
+    load_context: &mut LoadContext,
+    index: usize,
+    suffix: &str,
+    mesh: Mesh,
+    mirrored: bool,
+) -> (Handle<Mesh>, Option<Handle<Mesh>>) {
+    let mirrored = mirrored.then(|| {
+        load_context.set_labeled_asset(
+            &format!("model{index}/mirrored{suffix}"),
+            LoadedAsset::new(reversed_winding(&mesh)),
+        )
+    });
+    let mesh =
+        load_context.set_labeled_asset(&format!("model{index}{suffix}"), LoadedAsset::new(mesh));
+    (mesh, mirrored)
+}
+
+/// Splits the sub-meshes of each material slot from their mirrored copies, which only exist if the model is spawned mirrored.
+fn split_mirrored(
+    parts: Vec<(Handle<Mesh>, Option<Handle<Mesh>>)>,
+) -> (Vec<Handle<Mesh>>, Vec<Handle<Mesh>>) {
+    let (parts, mirrored): (Vec<_>, Vec<_>) = parts.into_iter().unzip();
+    (parts, mirrored.into_iter().flatten().collect())
+}
+
+/// Returns the sub-mesh of each material slot of a model, with its less detailed meshes.
+fn slot_parts(
+    slots: &[MaterialSlot],
+    parts: &[Handle<Mesh>],
+    lods: Vec<Vec<Handle<Mesh>>>,
+) -> Vec<SpawnedPart> {
+    parts
+        .iter()
+        .zip(lods)
+        .enumerate()
+        .map(|(slot, (handle, lods))| SpawnedPart {
+            name: slots[slot].label.clone(),
+            slot,
+            mesh: handle.clone(),
+            lods,
+        })
+        .collect()
+}
+
+/// Returns the sub-mesh of each chunk and material slot of a model which has voxels.
+fn chunk_parts(
+    slots: &[MaterialSlot],
+    chunk_parts: &[Vec<Option<Handle<Mesh>>>],
+) -> Vec<SpawnedPart> {
+    chunk_parts
+        .iter()
+        .enumerate()
+        .flat_map(|(chunk, parts)| {
+            parts.iter().enumerate().filter_map(move |(slot, handle)| {
+                Some(SpawnedPart {
+                    name: format!("chunk{chunk}/{}", slots[slot].label),
+                    slot,
+                    mesh: handle.clone()?,
+                    lods: Vec::new(),
+                })
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,89 @@
+use std::sync::Arc;
+
+use bevy::{prelude::*, render::primitives::Aabb};
+use block_mesh::QuadCoordinateConfig;
+
+use crate::{
+    material::PaletteMaterials,
+    mesh::{ModelMesher, ModelMeshes},
+    PivotMode, VoxLoaderSettings, VoxModel,
+};
+
+/// Swaps the mesh of an entity for a less detailed one as it gets further from the cameras.
+///
+/// The scene spawned for a `.vox` file inserts this component when [`VoxLoaderSettings::lod_levels`] is set.
+/// A [`crate::VoxPaletteSwap`] replaces the meshes with recolored copies, so that every level stays recolored.
+#[derive(Component, Reflect, Clone, Debug, Default)]
+#[reflect(Component)]
+pub struct VoxLod {
+    /// The meshes of each level of detail, starting with the most detailed one.
+    pub meshes: Vec<Handle<Mesh>>,
+    /// The distance to the nearest camera from which each level of detail after the first one is used.
+    pub distances: Vec<f32>,
+}
+
+impl VoxLod {
+    /// Returns the mesh to use at the given distance from the nearest camera.
+    pub fn mesh(&self, distance: f32) -> Option<&Handle<Mesh>> {
+        let level = self
+            .distances
+            .iter()
+            .take_while(|threshold| distance >= **threshold)
+            .count();
+        self.meshes.get(level).or_else(|| self.meshes.last())
+    }
+}
+
+pub(crate) fn select_lods(
+    mut commands: Commands,
+    cameras: Query<&GlobalTransform, With<Camera>>,
+    mut lods: Query<(Entity, &VoxLod, &GlobalTransform, &mut Handle<Mesh>)>,
+) {
+    for (entity, lod, transform, mut mesh) in lods.iter_mut() {
+        let distance = cameras
+            .iter()
+            .map(|camera| camera.translation().distance(transform.translation()))
+            .fold(f32::INFINITY, f32::min);
+        if let Some(lod_mesh) = lod.mesh(distance) {
+            if *mesh != *lod_mesh {
+                *mesh = lod_mesh.clone();
+                // the coarser voxels of a level can reach past the bounding box of the previous one, which bevy wouldn't recompute.
+                commands.entity(entity).remove::<Aabb>();
+            }
+        }
+    }
+}
+
+/// Returns the distances from which each level of detail after the first one is used,
+/// doubling with each level as the voxels double in size.
+pub(crate) fn lod_distances(settings: &VoxLoaderSettings) -> Vec<f32> {
+    (0..settings.lod_levels)
+        .map(|level| settings.lod_distance * 2f32.powi(level as i32))
+        .collect()
+}
+
+/// Meshes a level of detail of a model, where each voxel is `2^level` voxels of the model.
+///
+/// The meshes are aligned with the meshes of the model, but aren't updated when the model is edited.
+pub(crate) fn mesh_lod(
+    model: &VoxModel,
+    level: u32,
+    palette: Arc<[[f32; 4]]>,
+    materials: Arc<PaletteMaterials>,
+    quads_config: Arc<QuadCoordinateConfig>,
+    settings: &VoxLoaderSettings,
+) -> ModelMeshes {
+    let factor = 2u32.pow(level);
+    // the pivot is expressed in voxels of the level of detail, in MagicaVoxel coordinates.
+    let pivot = settings.pivot.pivot(model.magica_size()) / factor as f32;
+    let settings = VoxLoaderSettings {
+        voxel_size: settings.voxel_size * factor as f32,
+        pivot: PivotMode::Voxel(pivot.to_array()),
+        chunk_size: None,
+        ..settings.clone()
+    };
+
+    let lod_model = model.downsampled(factor);
+    ModelMesher::new(&lod_model, palette, materials, quads_config, &settings)
+        .mesh_loaded(&lod_model)
+}
@@ -1,46 +1,272 @@
 use bevy::asset::{Handle, LoadContext, LoadedAsset};
 use bevy::pbr::{AlphaMode, StandardMaterial};
+use bevy::prelude::{Color, Image};
+use block_mesh::VoxelVisibility;
 use dot_vox::Material;
 
+use crate::VoxLoadError;
+
 // constants used in magicavoxel's material property map
 const MATERIAL_TYPE: &str = "_type";
 const MATERIAL_ALPHA: &str = "_alpha";
 const MATERIAL_GLASS: &str = "_glass";
 const MATERIAL_EMIT: &str = "_emit";
+const MATERIAL_FLUX: &str = "_flux";
+const MATERIAL_DIFFUSE: &str = "_diffuse";
+const MATERIAL_METAL: &str = "_metal";
+const MATERIAL_ROUGH: &str = "_rough";
+const MATERIAL_SPEC: &str = "_spec";
+// MagicaVoxel stores the index of refraction minus one in `_ior`, newer versions also store the actual index in `_ri`.
+const MATERIAL_IOR: &str = "_ior";
+const MATERIAL_RI: &str = "_ri";
+
+/// The slot used by palette entries without a special material.
+pub(crate) const OPAQUE_SLOT: usize = 0;
+/// The slot used by the `_glass` palette entries with the properties of the first one.
+pub(crate) const TRANSPARENT_SLOT: usize = 1;
+
+/// A group of palette entries rendered with the same material, and thus meshed into their own sub-mesh.
+pub(crate) struct MaterialSlot {
+    /// The label suffix of the sub-meshes and material generated for this slot.
+    pub(crate) label: String,
+    properties: SlotProperties,
+}
+
+/// The physically based parameters shared by the palette entries of a slot.
+#[derive(Clone, Copy, PartialEq)]
+struct SlotProperties {
+    translucent: bool,
+    metallic: f32,
+    perceptual_roughness: f32,
+    reflectance: f32,
+    emissive: [f32; 3],
+}
 
-pub(crate) fn load_material(ctx: &mut LoadContext, palette: &[[f32; 4]], materials: &[Material]) -> Handle<StandardMaterial> {
-    if !materials.is_empty() {
-        assert_eq!(palette.len(), materials.len(), "Expected a material for every palette color");
+impl Default for SlotProperties {
+    fn default() -> Self {
+        let material = StandardMaterial::default();
+        Self {
+            translucent: false,
+            metallic: material.metallic,
+            perceptual_roughness: material.perceptual_roughness,
+            reflectance: material.reflectance,
+            emissive: [0.0; 3],
+        }
     }
+}
 
-    let (opaque, _emissive) = get_properties(palette, materials);
+impl SlotProperties {
+    fn kind(&self) -> &'static str {
+        if self.translucent {
+            "transparent"
+        } else if self.emissive != [0.0; 3] {
+            "emissive"
+        } else {
+            "opaque"
+        }
+    }
 
-    ctx.set_labeled_asset("material", LoadedAsset::new(StandardMaterial {
-        // TODO support emmission again
-        // emissive: if emissive { Color::WHITE } else { Color::BLACK },
-        alpha_mode: if opaque { AlphaMode::Opaque } else { AlphaMode::Blend },
-        ..StandardMaterial::default()
-    }))
+    fn to_material(self) -> StandardMaterial {
+        StandardMaterial {
+            alpha_mode: if self.translucent {
+                AlphaMode::Blend
+            } else {
+                AlphaMode::Opaque
+            },
+            metallic: self.metallic,
+            perceptual_roughness: self.perceptual_roughness,
+            reflectance: self.reflectance,
+            emissive: Color::rgba_linear(self.emissive[0], self.emissive[1], self.emissive[2], 1.0),
+            ..StandardMaterial::default()
+        }
+    }
+}
+
+/// Maps the palette entries of a `.vox` file to the material slot they are rendered with.
+pub(crate) struct PaletteMaterials {
+    pub(crate) slots: Vec<MaterialSlot>,
+    palette_slots: Vec<usize>,
 }
 
-fn get_properties(palette: &[[f32; 4]], materials: &[Material]) -> (bool, bool) {
-    let mut opaque = true;
-    let mut emissive = false;
+impl PaletteMaterials {
+    /// Sorts the palette entries into material slots, applying the glass opacity to the palette colors.
+    ///
+    /// Palette entries sharing the same material properties share a slot.
+    /// MagicaVoxel writes physically based parameters for every `_glass` entry, so the `transparent` slot takes the properties
+    /// of the first translucent entry, and only translucent entries with other properties get a slot of their own.
+    /// If `tolerant` is set, palette entries without a material use the default one instead of failing.
+    /// Palette colors are expected to be in the same color space as the mesh vertex colors, which bevy treats as linear.
+    pub(crate) fn new(
+        palette: &mut [[f32; 4]],
+        materials: &[Material],
+        tolerant: bool,
+    ) -> Result<Self, VoxLoadError> {
+        // some exporters write a shorter material list, the missing entries then use the default material.
+        if !tolerant && !materials.is_empty() && palette.len() != materials.len() {
+            return Err(VoxLoadError::InconsistentMaterials {
+                palette: palette.len(),
+                materials: materials.len(),
+            });
+        }
+
+        let mut slots = vec![
+            MaterialSlot {
+                label: "opaque".to_string(),
+                properties: SlotProperties::default(),
+            },
+            MaterialSlot {
+                label: "transparent".to_string(),
+                properties: SlotProperties {
+                    translucent: true,
+                    ..SlotProperties::default()
+                },
+            },
+        ];
 
-    for i in 0..palette.len() {
-        if let Some(material) = materials.get(i) {
-            if material.properties.get(MATERIAL_TYPE).filter(|x| *x == MATERIAL_GLASS).is_some() {
-                if let Some(_) = material.properties.get(MATERIAL_ALPHA).and_then(|x| x.parse::<f32>().ok()) {
-                    opaque = false;
-                }
+        let mut transparent_used = false;
+        let palette_slots = palette
+            .iter_mut()
+            .enumerate()
+            .map(|(i, color)| {
+                let properties = match materials.get(i) {
+                    Some(material) => get_properties(material, color),
+                    None => SlotProperties::default(),
+                };
+
+                let slot = match slots.iter().position(|slot| slot.properties == properties) {
+                    Some(slot) => slot,
+                    None if properties.translucent && !transparent_used => {
+                        slots[TRANSPARENT_SLOT].properties = properties;
+                        TRANSPARENT_SLOT
+                    }
+                    None => {
+                        slots.push(MaterialSlot {
+                            label: format!("{}{i}", properties.kind()),
+                            properties,
+                        });
+                        slots.len() - 1
+                    }
+                };
+                transparent_used |= slot == TRANSPARENT_SLOT;
+                slot
+            })
+            .collect();
+
+        Ok(Self {
+            slots,
+            palette_slots,
+        })
+    }
+
+    /// Returns the slot of the given palette entry.
+    pub(crate) fn slot(&self, index: u8) -> usize {
+        self.palette_slots
+            .get(index as usize)
+            .copied()
+            .unwrap_or(OPAQUE_SLOT)
+    }
+
+    /// Returns the meshing visibility of voxels using the given palette entry.
+    pub(crate) fn visibility(&self, index: u8) -> VoxelVisibility {
+        match self.slots[self.slot(index)].properties.translucent {
+            true => VoxelVisibility::Translucent,
+            false => VoxelVisibility::Opaque,
+        }
+    }
+
+    /// Registers the material of every slot as a labeled asset, using the palette texture as base color texture if there is one.
+    ///
+    /// The opaque material is labeled `material`, the others `material/{slot label}`.
+    pub(crate) fn load(
+        &self,
+        ctx: &mut LoadContext,
+        palette_texture: Option<Handle<Image>>,
+    ) -> Vec<Handle<StandardMaterial>> {
+        self.slots
+            .iter()
+            .enumerate()
+            .map(|(i, slot)| {
+                let label = match i {
+                    OPAQUE_SLOT => "material".to_string(),
+                    _ => format!("material/{}", slot.label),
+                };
+                let material = StandardMaterial {
+                    base_color_texture: palette_texture.clone(),
+                    ..slot.properties.to_material()
+                };
+                ctx.set_labeled_asset(&label, LoadedAsset::new(material))
+            })
+            .collect()
+    }
+}
+
+/// Reads the physically based parameters of a palette entry, applying the glass opacity to its color.
+///
+/// `_diffuse` entries keep the default parameters, as MagicaVoxel ignores the other properties for them.
+fn get_properties(material: &Material, color: &mut [f32; 4]) -> SlotProperties {
+    let mut properties = SlotProperties::default();
+
+    match material.properties.get(MATERIAL_TYPE).map(String::as_str) {
+        None | Some(MATERIAL_DIFFUSE) => return properties,
+        Some(MATERIAL_GLASS) => {
+            if let Some(opacity) = glass_opacity(material) {
+                color[3] *= opacity;
+                properties.translucent = true;
             }
-            if material.properties.get(MATERIAL_TYPE).filter(|x| *x == MATERIAL_EMIT).is_some() {
-                if let Some(_) = material.properties.get(MATERIAL_EMIT).and_then(|x| x.parse::<f32>().ok()) {
-                    emissive = true;
-                }
+        }
+        Some(MATERIAL_EMIT) => {
+            if let Some(strength) = emission_strength(material) {
+                properties.emissive = [
+                    color[0] * strength,
+                    color[1] * strength,
+                    color[2] * strength,
+                ];
             }
         }
+        _ => {}
+    }
+
+    if let Some(metal) = get_property(material, MATERIAL_METAL) {
+        properties.metallic = metal.clamp(0.0, 1.0);
+    }
+    if let Some(rough) = get_property(material, MATERIAL_ROUGH) {
+        properties.perceptual_roughness = rough.clamp(0.0, 1.0);
+    }
+    // bevy's reflectance is the same remapping of the fresnel reflectance at normal incidence as the usual specular parameter,
+    // so `_spec` maps to it directly, and the index of refraction is only used if it is missing.
+    if let Some(spec) = get_property(material, MATERIAL_SPEC) {
+        properties.reflectance = spec.clamp(0.0, 1.0);
+    } else if let Some(ior) = get_property(material, MATERIAL_RI)
+        .or_else(|| get_property(material, MATERIAL_IOR).map(|ior| ior + 1.0))
+    {
+        // bevy computes the reflectance at normal incidence as `0.16 * reflectance^2`.
+        let f0 = ((ior - 1.0) / (ior + 1.0)).powi(2);
+        properties.reflectance = (f0 / 0.16).sqrt().clamp(0.0, 1.0);
     }
 
-    (opaque, emissive)
+    properties
+}
+
+fn get_property(material: &Material, key: &str) -> Option<f32> {
+    material
+        .properties
+        .get(key)
+        .and_then(|x| x.parse::<f32>().ok())
+}
+
+/// Returns the opacity of a `_glass` material, or `None` if the material doesn't specify one.
+///
+/// MagicaVoxel stores the transparency of glass in its `_alpha` property.
+fn glass_opacity(material: &Material) -> Option<f32> {
+    get_property(material, MATERIAL_ALPHA).map(|alpha| 1.0 - alpha.clamp(0.0, 1.0))
+}
+
+/// Returns the emission strength of an `_emit` material, or `None` if the material doesn't glow.
+///
+/// The `_emit` property is scaled by the MagicaVoxel "power" setting stored in `_flux`, which ranges from 0 to 4.
+fn emission_strength(material: &Material) -> Option<f32> {
+    let emit = get_property(material, MATERIAL_EMIT).filter(|emit| *emit > 0.0)?;
+    let flux = get_property(material, MATERIAL_FLUX).unwrap_or(0.0);
+
+    Some(emit * (1.0 + flux))
 }
@@ -1,118 +1,717 @@
+use std::sync::Arc;
+
 use bevy::{
-    prelude::Vec3,
+    pbr::StandardMaterial,
+    prelude::{Handle, UVec3, Vec3},
     render::{
-        mesh::{Indices, Mesh, VertexAttributeValues},
-        render_resource::PrimitiveTopology,
+        mesh::{Indices, Mesh, MeshVertexAttribute, VertexAttributeValues},
+        render_resource::{PrimitiveTopology, VertexFormat},
     },
 };
-use block_mesh::{greedy_quads, GreedyQuadsBuffer, QuadCoordinateConfig};
+use block_mesh::{
+    greedy_quads, visible_block_faces, GreedyQuadsBuffer, QuadCoordinateConfig, UnitQuadBuffer,
+    UnorientedQuad, VoxelVisibility,
+};
+use fast_surface_nets::{surface_nets, SurfaceNetsBuffer};
 use ndshape::{RuntimeShape, Shape};
 
-use crate::voxel::Voxel;
-
-pub(crate) fn mesh_model(
-    buffer_shape: RuntimeShape<u32, 3>,
-    buffer: &[Voxel],
-    palette: &[[f32; 4]],
-    quads_config: &QuadCoordinateConfig,
-    v_flip_face: bool,
-    translate: Option<Vec3>,
-) -> Mesh {
-    let mut greedy_quads_buffer = GreedyQuadsBuffer::new(buffer_shape.size() as usize);
-
-    greedy_quads(
-        buffer,
-        &buffer_shape,
-        [0; 3],
-        buffer_shape.as_array().map(|x| x - 1),
-        &quads_config.faces,
-        &mut greedy_quads_buffer,
-    );
-
-    let num_indices = greedy_quads_buffer.quads.num_quads() * 6;
-    let num_vertices = greedy_quads_buffer.quads.num_quads() * 4;
-
-    let mut indices = Vec::with_capacity(num_indices);
-    let mut positions = Vec::with_capacity(num_vertices);
-    let mut normals = Vec::with_capacity(num_vertices);
-    let mut uvs = Vec::with_capacity(num_vertices);
-    let mut colors = Vec::with_capacity(num_vertices);
-
-    let mut render_mesh = Mesh::new(PrimitiveTopology::TriangleList);
-
-    let translate_center = buffer_shape
-        .as_array()
-        .map(|p| ((p - 2) as f32 * 0.5).floor());
-
-    let translate = match translate {
-        Some(translate) => [
-            translate[0] - translate_center[0],
-            translate[1] - translate_center[1],
-            translate[2] - translate_center[2],
-        ],
-        None => translate_center,
-    };
-
-    for (group, face) in greedy_quads_buffer
-        .quads
-        .groups
-        .iter()
-        .zip(quads_config.faces.as_ref())
-    {
-        for quad in group.iter() {
-            // we reverse x since MagicaVoxel's x axis is reversed
-            indices.extend_from_slice(&{
-                let mut next = face.quad_mesh_indices(positions.len() as u32);
-                next.swap(0, 2);
-                next.swap(3, 5);
-                next
-            });
-
-            let translate_x = |mut vec: [f32; 3]| {
-                vec[0] = vec[0] + translate[0];
-                vec[1] = vec[1] + translate[1];
-                vec[2] = vec[2] + translate[2];
-                vec[2] = -vec[2];
-                vec
-            };
+use crate::{
+    material::PaletteMaterials,
+    occlusion::{flip_diagonal, occlude_voxels, occlusion_factor, quad_occlusion},
+    palette::palette_uv,
+    voxel::{load_region, Voxel, EMPTY_VOXEL},
+    AxisConvention, ColorMode, MeshingAlgorithm, VoxLoaderSettings, VoxModel,
+};
+
+/// The meshes generated for a single model.
+pub(crate) struct ModelMeshes {
+    /// The whole model in a single mesh.
+    pub(crate) mesh: Mesh,
+    /// A sub-mesh per material slot, `None` if no voxel of the model uses the slot.
+    pub(crate) parts: Vec<Option<Mesh>>,
+    /// The index and the sub-meshes per material slot of the chunks which were meshed,
+    /// if the model is split into chunks with [`VoxLoaderSettings::chunk_size`].
+    pub(crate) chunks: Vec<(usize, Vec<Option<Mesh>>)>,
+}
+
+/// A vertex attribute holding the palette index of the voxel a vertex belongs to, used to recolor meshes at runtime.
+pub const ATTRIBUTE_PALETTE_INDEX: MeshVertexAttribute =
+    MeshVertexAttribute::new("Vertex_PaletteIndex", 2_481_964_725, VertexFormat::Uint32);
+
+/// A vertex attribute holding the ambient occlusion factor baked into the vertex colors, `1.0` if the vertex isn't occluded.
+pub const ATTRIBUTE_OCCLUSION: MeshVertexAttribute =
+    MeshVertexAttribute::new("Vertex_Occlusion", 2_481_964_726, VertexFormat::Float32);
+
+/// The attributes of a single vertex.
+#[derive(Clone, Copy)]
+struct Vertex {
+    position: [f32; 3],
+    normal: [f32; 3],
+    uv: [f32; 2],
+    color: [f32; 4],
+    palette_index: u8,
+    occlusion: f32,
+}
+
+#[derive(Clone, Default)]
+struct MeshBuffers {
+    indices: Vec<u32>,
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    uvs: Vec<[f32; 2]>,
+    colors: Vec<[f32; 4]>,
+    palette_indices: Vec<u32>,
+    occlusion: Vec<f32>,
+}
+
+impl MeshBuffers {
+    fn push_vertex(&mut self, vertex: Vertex) -> u32 {
+        self.positions.push(vertex.position);
+        self.normals.push(vertex.normal);
+        self.uvs.push(vertex.uv);
+        self.colors.push(vertex.color);
+        self.palette_indices.push(vertex.palette_index as u32);
+        self.occlusion.push(vertex.occlusion);
+        self.positions.len() as u32 - 1
+    }
+
+    fn push_quad(&mut self, indices: [u32; 6], vertices: [Vertex; 4]) {
+        let start = self.positions.len() as u32;
+        self.indices.extend(indices.map(|index| index + start));
+        for vertex in vertices {
+            self.push_vertex(vertex);
+        }
+    }
+
+    /// Appends the vertices and indices of other buffers.
+    fn extend(&mut self, other: &MeshBuffers) {
+        let start = self.positions.len() as u32;
+        self.indices
+            .extend(other.indices.iter().map(|index| index + start));
+        self.positions.extend_from_slice(&other.positions);
+        self.normals.extend_from_slice(&other.normals);
+        self.uvs.extend_from_slice(&other.uvs);
+        self.colors.extend_from_slice(&other.colors);
+        self.palette_indices
+            .extend_from_slice(&other.palette_indices);
+        self.occlusion.extend_from_slice(&other.occlusion);
+    }
+
+    fn into_mesh(self) -> Mesh {
+        let mut render_mesh = Mesh::new(PrimitiveTopology::TriangleList);
+
+        render_mesh.insert_attribute(
+            Mesh::ATTRIBUTE_POSITION,
+            VertexAttributeValues::Float32x3(self.positions),
+        );
+
+        render_mesh.insert_attribute(
+            Mesh::ATTRIBUTE_NORMAL,
+            VertexAttributeValues::Float32x3(self.normals),
+        );
+        render_mesh.insert_attribute(
+            Mesh::ATTRIBUTE_UV_0,
+            VertexAttributeValues::Float32x2(self.uvs),
+        );
+
+        render_mesh.insert_attribute(
+            Mesh::ATTRIBUTE_COLOR,
+            VertexAttributeValues::Float32x4(self.colors),
+        );
+
+        render_mesh.insert_attribute(
+            ATTRIBUTE_PALETTE_INDEX,
+            VertexAttributeValues::Uint32(self.palette_indices),
+        );
+        render_mesh.insert_attribute(
+            ATTRIBUTE_OCCLUSION,
+            VertexAttributeValues::Float32(self.occlusion),
+        );
+
+        // smaller meshes such as chunks can use smaller indices.
+        let indices = match self.positions.len() <= u16::MAX as usize + 1 {
+            true => Indices::U16(self.indices.into_iter().map(|index| index as u16).collect()),
+            false => Indices::U32(self.indices),
+        };
+        render_mesh.set_indices(Some(indices));
+
+        render_mesh
+    }
+}
+
+/// Returns a mesh without any vertex, with the same attributes as the generated meshes.
+pub(crate) fn empty_mesh() -> Mesh {
+    MeshBuffers::default().into_mesh()
+}
+
+/// Returns a copy of a mesh with the winding of its triangles reversed,
+/// so that its front faces stay in front when it is rendered with a negative scale.
+pub(crate) fn reversed_winding(mesh: &Mesh) -> Mesh {
+    let mut mesh = mesh.clone();
+    match mesh.indices_mut() {
+        Some(Indices::U16(indices)) => indices
+            .chunks_exact_mut(3)
+            .for_each(|triangle| triangle.swap(1, 2)),
+        Some(Indices::U32(indices)) => indices
+            .chunks_exact_mut(3)
+            .for_each(|triangle| triangle.swap(1, 2)),
+        None => {}
+    }
+    mesh
+}
+
+/// Converts positions and normals from the voxel buffer space into the mesh space.
+struct MeshTransform {
+    pivot: [f32; 3],
+    voxel_size: f32,
+    axis_convention: AxisConvention,
+    /// The position of the buffer in the model, when only a region of the model is meshed.
+    offset: [u32; 3],
+}
+
+impl MeshTransform {
+    /// Creates the transform of a model with the given size, in the buffer axes.
+    fn new(size: [u32; 3], settings: &VoxLoaderSettings) -> Self {
+        // the buffer axes are MagicaVoxel's axes in (x, z, y) order.
+        let pivot = settings.pivot.pivot([size[0], size[2], size[1]]);
+        Self {
+            pivot: [pivot.x, pivot.z, pivot.y],
+            voxel_size: settings.voxel_size,
+            axis_convention: settings.axis_convention,
+            offset: [0; 3],
+        }
+    }
+
+    fn position(&self, position: [f32; 3]) -> [f32; 3] {
+        // corrects the 1 offset introduced by the padding of the buffer.
+        let vec = [
+            position[0] + self.offset[0] as f32 - 1.0,
+            position[1] + self.offset[1] as f32 - 1.0,
+            position[2] + self.offset[2] as f32 - 1.0,
+        ];
+        let vec = [
+            vec[0] - self.pivot[0],
+            vec[1] - self.pivot[1],
+            vec[2] - self.pivot[2],
+        ];
+        (self.to_mesh_axes(vec) * self.voxel_size).to_array()
+    }
+
+    fn normal(&self, normal: [f32; 3]) -> [f32; 3] {
+        self.to_mesh_axes(normal).to_array()
+    }
+
+    /// Whether the buffer axes are mirrored in the mesh axes, in which case the winding of the triangles must be reversed
+    /// for them to keep facing out.
+    fn reverses_winding(&self) -> bool {
+        !self.axis_convention.is_left_handed()
+    }
+
+    /// Converts a vector from the buffer axes into the mesh axes.
+    ///
+    /// The buffer axes are a mirror of MagicaVoxel's axes, so they're mirrored in the mesh axes of right-handed conventions,
+    /// and not in those of left-handed conventions, which the winding of the triangles accounts for.
+    fn to_mesh_axes(&self, vec: [f32; 3]) -> Vec3 {
+        self.axis_convention
+            .convert(Vec3::new(vec[0], vec[2], vec[1]))
+    }
+}
+
+/// Returns the position of the corner of a model with the lowest coordinates in the space of its meshes,
+/// given the size of the model in MagicaVoxel's axes.
+pub(crate) fn mesh_origin(size: [u32; 3], settings: &VoxLoaderSettings) -> Vec3 {
+    let size = [size[0], size[2], size[1]];
+    let transform = MeshTransform::new(size, settings);
+    // buffer axes may be reversed in the mesh, so the lowest corner is made of the lowest coordinates of opposite corners.
+    let min = transform.position([1.0; 3]);
+    let max = transform.position(size.map(|size| size as f32 + 1.0));
+    Vec3::from(min).min(Vec3::from(max))
+}
+
+/// The size of the chunks a model is meshed in once it is edited, when it isn't split into chunks,
+/// so that editing a voxel only remeshes the chunks around it.
+const CHUNK_SIZE: u32 = 32;
+
+/// Meshes the voxels of a model chunk by chunk, keeping the vertices of every chunk so that only edited chunks need to be meshed again.
+#[derive(Clone)]
+pub(crate) struct ModelMesher {
+    palette: Arc<[[f32; 4]]>,
+    materials: Arc<PaletteMaterials>,
+    quads_config: Arc<QuadCoordinateConfig>,
+    settings: VoxLoaderSettings,
+    /// The number of chunks along each buffer axis.
+    chunks_count: [u32; 3],
+    chunk_size: u32,
+    /// Whether a mesh is generated for each chunk.
+    split: bool,
+    /// The vertices of each chunk and material slot, `None` if the chunk needs to be meshed.
+    chunks: Vec<Option<Vec<MeshBuffers>>>,
+    /// Whether voxels were edited since the model was last meshed.
+    dirty: bool,
+    /// Whether chunks were meshed since the meshes of the whole model and of each material slot were last generated.
+    stale: bool,
+    /// The meshes of the whole model, updated in place when the model is remeshed.
+    pub(crate) meshes: Vec<Handle<Mesh>>,
+    /// The meshes of each material slot generated when the model was loaded.
+    pub(crate) parts: Vec<Handle<Mesh>>,
+    /// The meshes of each chunk and material slot, if the model is split into chunks.
+    /// They're `None` until the chunk has voxels using the slot.
+    pub(crate) chunk_parts: Vec<Vec<Option<Handle<Mesh>>>>,
+    /// The copies with reversed winding of the meshes of the whole model, of each material slot and of each chunk,
+    /// which are empty unless the model is spawned mirrored.
+    pub(crate) mirrored_meshes: Vec<Handle<Mesh>>,
+    pub(crate) mirrored_parts: Vec<Handle<Mesh>>,
+    pub(crate) mirrored_chunk_parts: Vec<Vec<Option<Handle<Mesh>>>>,
+    /// The material of each material slot, given to the entities spawned for the chunks which edits add voxels to.
+    pub(crate) slot_materials: Vec<Handle<StandardMaterial>>,
+}
+
+impl ModelMesher {
+    pub(crate) fn new(
+        model: &VoxModel,
+        palette: Arc<[[f32; 4]]>,
+        materials: Arc<PaletteMaterials>,
+        quads_config: Arc<QuadCoordinateConfig>,
+        settings: &VoxLoaderSettings,
+    ) -> Self {
+        let size = buffer_size(model);
+        // surface nets would leave seams between chunks, so the surface is always meshed at once.
+        let chunk_size = match (settings.meshing, settings.chunk_size) {
+            (MeshingAlgorithm::SurfaceNets, _) => size.into_iter().max().unwrap_or(1).max(1),
+            (_, Some(chunk_size)) => chunk_size.max(1),
+            (_, None) => CHUNK_SIZE,
+        };
+        let chunks_count = size.map(|size| (size + chunk_size - 1) / chunk_size);
+
+        Self {
+            palette,
+            materials,
+            quads_config,
+            settings: settings.clone(),
+            chunks_count,
+            chunk_size,
+            split: settings.chunk_size.is_some()
+                && settings.meshing != MeshingAlgorithm::SurfaceNets,
+            chunks: vec![None; chunks_count.iter().product::<u32>() as usize],
+            dirty: false,
+            stale: false,
+            meshes: Vec::new(),
+            parts: Vec::new(),
+            chunk_parts: Vec::new(),
+            mirrored_meshes: Vec::new(),
+            mirrored_parts: Vec::new(),
+            mirrored_chunk_parts: Vec::new(),
+            slot_materials: Vec::new(),
+        }
+    }
+
+    /// Marks the chunks containing the voxels between two grid positions for remeshing, along with the chunks bordering them.
+    pub(crate) fn mark_dirty(&mut self, model: &VoxModel, min: UVec3, max: UVec3) {
+        // grid axes may be reversed compared to the buffer, so the corners are converted before sorting their coordinates.
+        let (corner, opposite) = (model.magica_position(min), model.magica_position(max));
+        let (corner, opposite) = (
+            [corner[0], corner[2], corner[1]],
+            [opposite[0], opposite[2], opposite[1]],
+        );
+        let min = [0, 1, 2].map(|axis| corner[axis].min(opposite[axis]));
+        let max = [0, 1, 2].map(|axis| corner[axis].max(opposite[axis]));
+
+        let chunk_min = min.map(|p| p.saturating_sub(1) / self.chunk_size);
+        let chunk_max = [0, 1, 2]
+            .map(|axis| ((max[axis] + 1) / self.chunk_size).min(self.chunks_count[axis] - 1));
+        for z in chunk_min[2]..=chunk_max[2] {
+            for y in chunk_min[1]..=chunk_max[1] {
+                for x in chunk_min[0]..=chunk_max[0] {
+                    let chunk = self.chunk_index([x, y, z]);
+                    self.chunks[chunk] = None;
+                }
+            }
+        }
+        self.dirty = true;
+    }
+
+    /// Whether voxels were edited since the model was last meshed.
+    pub(crate) fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Whether the meshes of the whole model and of each material slot are older than the meshes of the chunks.
+    pub(crate) fn is_stale(&self) -> bool {
+        self.stale
+    }
+
+    /// Whether a mesh is generated for each chunk, which the spawned scene uses instead of the meshes of the whole model.
+    pub(crate) fn is_split(&self) -> bool {
+        self.split
+    }
 
-            let negate_z = |mut vec: [f32; 3]| {
-                vec[2] = -vec[2];
-                vec
+    /// Whether the model is spawned mirrored, so its meshes have copies with reversed winding.
+    pub(crate) fn is_mirrored(&self) -> bool {
+        !self.mirrored_meshes.is_empty()
+    }
+
+    /// The label of a material slot, which names the entities of its sub-meshes.
+    pub(crate) fn slot_label(&self, slot: usize) -> &str {
+        &self.materials.slots[slot].label
+    }
+
+    /// Iterates over the handles of the meshes of the whole model and of each material slot, including their mirrored copies.
+    pub(crate) fn whole_meshes(&self) -> impl Iterator<Item = &Handle<Mesh>> {
+        self.meshes
+            .iter()
+            .chain(&self.parts)
+            .chain(&self.mirrored_meshes)
+            .chain(&self.mirrored_parts)
+    }
+
+    /// Meshes a model as it is loaded.
+    ///
+    /// Models which aren't split into chunks are meshed at once, so that greedy quads don't stop at the borders of chunks,
+    /// and are only meshed chunk by chunk the first time they're edited.
+    pub(crate) fn mesh_loaded(&mut self, model: &VoxModel) -> ModelMeshes {
+        if self.split {
+            let chunks = self.mesh_chunks(model);
+            return ModelMeshes {
+                chunks,
+                ..self.mesh_whole()
             };
+        }
+        let parts = self.mesh_region(model, [0; 3], buffer_size(model));
+        into_model_meshes(parts, Vec::new())
+    }
+
+    /// Meshes the chunks which need it, and returns the sub-meshes of the chunks which were meshed if the model is split into chunks.
+    ///
+    /// The meshes of the whole model are then stale until they're generated again with [`ModelMesher::mesh_whole`].
+    pub(crate) fn mesh_chunks(&mut self, model: &VoxModel) -> Vec<(usize, Vec<Option<Mesh>>)> {
+        let mut chunks = Vec::new();
+        for z in 0..self.chunks_count[2] {
+            for y in 0..self.chunks_count[1] {
+                for x in 0..self.chunks_count[0] {
+                    let chunk = self.chunk_index([x, y, z]);
+                    if self.chunks[chunk].is_none() {
+                        let parts = self.mesh_chunk(model, [x, y, z]);
+                        if self.split {
+                            chunks.push((chunk, parts.iter().cloned().map(into_part).collect()));
+                        }
+                        self.chunks[chunk] = Some(parts);
+                    }
+                }
+            }
+        }
+        self.dirty = false;
+        self.stale = true;
+        chunks
+    }
+
+    /// Returns the meshes of the whole model and of each material slot, made of the vertices of the chunks meshed so far.
+    pub(crate) fn mesh_whole(&mut self) -> ModelMeshes {
+        let mut parts: Vec<MeshBuffers> = self
+            .materials
+            .slots
+            .iter()
+            .map(|_| MeshBuffers::default())
+            .collect();
+        for chunk in self.chunks.iter().flatten() {
+            for (part, chunk_part) in parts.iter_mut().zip(chunk) {
+                part.extend(chunk_part);
+            }
+        }
+        self.stale = false;
+        into_model_meshes(parts, Vec::new())
+    }
+
+    fn chunk_index(&self, chunk: [u32; 3]) -> usize {
+        (chunk[0] + self.chunks_count[0] * (chunk[1] + self.chunks_count[1] * chunk[2])) as usize
+    }
+
+    /// Meshes a single chunk, returning its vertices for each material slot.
+    fn mesh_chunk(&self, model: &VoxModel, chunk: [u32; 3]) -> Vec<MeshBuffers> {
+        let model_size = buffer_size(model);
+        let min = chunk.map(|c| c * self.chunk_size);
+        let size = [0, 1, 2].map(|axis| self.chunk_size.min(model_size[axis] - min[axis]));
+        self.mesh_region(model, min, size)
+    }
+
+    /// Meshes the voxels of a region of the model, in the buffer axes, returning its vertices for each material slot.
+    fn mesh_region(&self, model: &VoxModel, min: [u32; 3], size: [u32; 3]) -> Vec<MeshBuffers> {
+        let model_size = buffer_size(model);
+        let (buffer_shape, buffer) = load_region(model, &self.materials, min, size);
+
+        let mut transform = MeshTransform::new(model_size, &self.settings);
+        transform.offset = min;
+
+        let mesher = Mesher {
+            buffer_shape: &buffer_shape,
+            buffer: &buffer,
+            palette: &self.palette,
+            materials: &self.materials,
+            settings: &self.settings,
+            transform,
+        };
 
-            positions.extend_from_slice(
-                &face
+        let mut parts: Vec<MeshBuffers> = self
+            .materials
+            .slots
+            .iter()
+            .map(|_| MeshBuffers::default())
+            .collect();
+        match self.settings.meshing {
+            MeshingAlgorithm::Greedy | MeshingAlgorithm::Culled => {
+                mesher.mesh_quads(&self.quads_config, &mut parts)
+            }
+            MeshingAlgorithm::SurfaceNets => mesher.mesh_surface(&mut parts),
+        }
+        parts
+    }
+}
+
+/// Turns the vertices of each material slot into the mesh of the whole model and its sub-meshes.
+fn into_model_meshes(
+    parts: Vec<MeshBuffers>,
+    chunks: Vec<(usize, Vec<Option<Mesh>>)>,
+) -> ModelMeshes {
+    let mut mesh = MeshBuffers::default();
+    for part in parts.iter() {
+        mesh.extend(part);
+    }
+
+    ModelMeshes {
+        mesh: mesh.into_mesh(),
+        parts: parts.into_iter().map(into_part).collect(),
+        chunks,
+    }
+}
+
+/// Turns the vertices of a material slot into a sub-mesh, `None` if there are no vertices.
+fn into_part(part: MeshBuffers) -> Option<Mesh> {
+    (!part.indices.is_empty()).then(|| part.into_mesh())
+}
+
+/// Returns the size of a model in the buffer axes.
+fn buffer_size(model: &VoxModel) -> [u32; 3] {
+    let size = model.magica_size();
+    [size[0], size[2], size[1]]
+}
+
+/// The voxels of a model, along with what is needed to turn them into meshes.
+struct Mesher<'a> {
+    buffer_shape: &'a RuntimeShape<u32, 3>,
+    buffer: &'a [Voxel],
+    palette: &'a [[f32; 4]],
+    materials: &'a PaletteMaterials,
+    settings: &'a VoxLoaderSettings,
+    transform: MeshTransform,
+}
+
+impl<'a> Mesher<'a> {
+    /// Returns the vertex color of a voxel, and the UVs of its palette entry if the palette texture is used instead.
+    fn voxel_color(&self, palette_index: u8) -> ([f32; 4], Option<[f32; 2]>) {
+        match self.settings.color_mode {
+            ColorMode::VertexColors => (
+                self.palette
+                    .get(palette_index as usize)
+                    .copied()
+                    .unwrap_or([1.0; 4]),
+                None,
+            ),
+            ColorMode::PaletteTexture => (
+                [1.0; 4],
+                Some(palette_uv(palette_index, self.palette.len())),
+            ),
+        }
+    }
+
+    fn mesh_quads(&self, quads_config: &QuadCoordinateConfig, parts: &mut [MeshBuffers]) {
+        let (buffer_shape, buffer, materials, settings, transform) = (
+            self.buffer_shape,
+            self.buffer,
+            self.materials,
+            self.settings,
+            &self.transform,
+        );
+
+        let groups: [Vec<UnorientedQuad>; 6] = match settings.meshing {
+            MeshingAlgorithm::Culled => {
+                let mut unit_quads_buffer = UnitQuadBuffer::new();
+                visible_block_faces(
+                    buffer,
+                    buffer_shape,
+                    [0; 3],
+                    buffer_shape.as_array().map(|x| x - 1),
+                    &quads_config.faces,
+                    &mut unit_quads_buffer,
+                );
+                unit_quads_buffer
+                    .groups
+                    .map(|group| group.into_iter().map(UnorientedQuad::from).collect())
+            }
+            _ => {
+                let mut greedy_quads_buffer = GreedyQuadsBuffer::new(buffer_shape.size() as usize);
+                if settings.ambient_occlusion {
+                    greedy_quads(
+                        &occlude_voxels(buffer_shape, buffer),
+                        buffer_shape,
+                        [0; 3],
+                        buffer_shape.as_array().map(|x| x - 1),
+                        &quads_config.faces,
+                        &mut greedy_quads_buffer,
+                    );
+                } else {
+                    greedy_quads(
+                        buffer,
+                        buffer_shape,
+                        [0; 3],
+                        buffer_shape.as_array().map(|x| x - 1),
+                        &quads_config.faces,
+                        &mut greedy_quads_buffer,
+                    );
+                }
+                greedy_quads_buffer.quads.groups
+            }
+        };
+
+        for (group, face) in groups.iter().zip(quads_config.faces.as_ref()) {
+            for quad in group.iter() {
+                let occlusion = settings.ambient_occlusion.then(|| {
+                    quad_occlusion(
+                        buffer_shape,
+                        buffer,
+                        &face.quad_mesh_positions(quad, 1.0),
+                        face.quad_mesh_normals()[0],
+                    )
+                });
+
+                let indices = {
+                    let mut next = face.quad_mesh_indices(0);
+                    if occlusion.map_or(false, flip_diagonal) {
+                        // rotating the corners moves the diagonal between the first and last corners.
+                        next = next.map(|index| [1, 3, 0, 2][index as usize]);
+                    }
+                    if transform.reverses_winding() {
+                        next.swap(0, 2);
+                        next.swap(3, 5);
+                    }
+                    next
+                };
+
+                let positions = face
                     .quad_mesh_positions(quad, 1.0)
-                    .map(|position| position.map(|x| x - 1.0)) // corrects the 1 offset introduced by the meshing.
-                    .map(translate_x),
-            );
+                    .map(|position| transform.position(position));
 
-            normals.extend_from_slice(&face.quad_mesh_normals().map(negate_z));
+                let normals = face
+                    .quad_mesh_normals()
+                    .map(|normal| transform.normal(normal));
 
-            let palette_index = buffer[buffer_shape.linearize(quad.minimum) as usize].0;
-            colors.extend_from_slice(&[palette[palette_index as usize]; 4]);
-            uvs.extend_from_slice(&face.tex_coords(quads_config.u_flip_face, v_flip_face, quad));
+                let palette_index = buffer[buffer_shape.linearize(quad.minimum) as usize].0;
+                let (color, palette_uv) = self.voxel_color(palette_index);
+                let occlusion =
+                    occlusion.map_or([1.0; 4], |occlusion| occlusion.map(occlusion_factor));
+                let uvs = match palette_uv {
+                    Some(uv) => [uv; 4],
+                    None => face.tex_coords(quads_config.u_flip_face, settings.v_flip_faces, quad),
+                };
+
+                let vertices = [0, 1, 2, 3].map(|i| Vertex {
+                    position: positions[i],
+                    normal: normals[i],
+                    uv: uvs[i],
+                    color: [
+                        color[0] * occlusion[i],
+                        color[1] * occlusion[i],
+                        color[2] * occlusion[i],
+                        color[3],
+                    ],
+                    palette_index,
+                    occlusion: occlusion[i],
+                });
+
+                parts[materials.slot(palette_index)].push_quad(indices, vertices);
+            }
         }
     }
 
-    render_mesh.insert_attribute(
-        Mesh::ATTRIBUTE_POSITION,
-        VertexAttributeValues::Float32x3(positions),
-    );
+    /// Meshes the occupancy of the voxels as a smooth surface.
+    ///
+    /// The vertices of the surface don't map to voxel faces, so their UVs are left at zero unless the palette texture is used.
+    fn mesh_surface(&self, parts: &mut [MeshBuffers]) {
+        let (buffer_shape, buffer, materials, transform) = (
+            self.buffer_shape,
+            self.buffer,
+            self.materials,
+            &self.transform,
+        );
 
-    render_mesh.insert_attribute(
-        Mesh::ATTRIBUTE_NORMAL,
-        VertexAttributeValues::Float32x3(normals),
-    );
-    render_mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, VertexAttributeValues::Float32x2(uvs));
+        let sdf: Vec<f32> = buffer
+            .iter()
+            .map(|voxel| match voxel.1 {
+                VoxelVisibility::Empty => 0.5,
+                _ => -0.5,
+            })
+            .collect();
 
-    render_mesh.insert_attribute(
-        Mesh::ATTRIBUTE_COLOR,
-        VertexAttributeValues::Float32x4(colors),
-    );
+        let mut surface_nets_buffer = SurfaceNetsBuffer::default();
+        surface_nets(
+            &sdf,
+            buffer_shape,
+            [0; 3],
+            buffer_shape.as_array().map(|x| x - 1),
+            &mut surface_nets_buffer,
+        );
 
-    render_mesh.set_indices(Some(Indices::U32(indices.clone())));
+        // every vertex takes the color of a voxel of the cell it lies in.
+        let vertex_voxels: Vec<Voxel> = surface_nets_buffer
+            .surface_points
+            .iter()
+            .map(|point| {
+                (0..8)
+                    .map(|corner| {
+                        buffer[buffer_shape.linearize([
+                            point[0] + (corner & 1),
+                            point[1] + ((corner >> 1) & 1),
+                            point[2] + (corner >> 2),
+                        ]) as usize]
+                    })
+                    .find(|voxel| voxel.1 != VoxelVisibility::Empty)
+                    .unwrap_or(EMPTY_VOXEL)
+            })
+            .collect();
 
-    render_mesh
+        let vertices = surface_nets_buffer
+            .positions
+            .iter()
+            .zip(surface_nets_buffer.normals.iter())
+            .zip(vertex_voxels.iter())
+            .map(|((position, normal), voxel)| {
+                // samples are taken at the voxel centers, while the voxel faces are at whole coordinates.
+                let position = transform.position(position.map(|x| x + 0.5));
+                let normal = transform.normal(Vec3::from(*normal).normalize_or_zero().to_array());
+                let (color, uv) = self.voxel_color(voxel.0);
+                Vertex {
+                    position,
+                    normal,
+                    uv: uv.unwrap_or_default(),
+                    color,
+                    palette_index: voxel.0,
+                    occlusion: 1.0,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut part_vertices = vec![vec![None; vertices.len()]; parts.len()];
+        for triangle in surface_nets_buffer.indices.chunks_exact(3) {
+            let triangle = match transform.reverses_winding() {
+                true => [triangle[0], triangle[2], triangle[1]],
+                false => [triangle[0], triangle[1], triangle[2]],
+            };
+
+            let slot = materials.slot(vertex_voxels[triangle[0] as usize].0);
+            let part = &mut parts[slot];
+            for vertex in triangle {
+                let index = *part_vertices[slot][vertex as usize]
+                    .get_or_insert_with(|| part.push_vertex(vertices[vertex as usize]));
+                part.indices.push(index);
+            }
+        }
+    }
 }
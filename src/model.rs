@@ -0,0 +1,541 @@
+use bevy::{
+    ecs::system::SystemParam, prelude::*, reflect::TypeUuid, render::primitives::Aabb,
+    utils::HashSet,
+};
+use dot_vox::Model;
+
+use crate::{
+    error::MAX_MODEL_SIZE,
+    mesh::{empty_mesh, reversed_winding, ModelMesher},
+    palette::PaletteSwapped,
+    scene::{part_bundle, ChunkedShape},
+    AxisConvention, VoxFlipbook, VoxLayer, VoxLoadError, VoxLod, VoxPalette,
+};
+
+/// The palette index marking empty voxels, which MagicaVoxel never uses for a voxel.
+const EMPTY: u8 = 255;
+
+/// The voxels of a model of a `.vox` file, labeled `#voxels{no}`.
+///
+/// The voxels are stored in a grid with the same axes as the generated meshes, set by [`crate::AxisConvention`],
+/// and the grid position `(0, 0, 0)` is the voxel with the lowest coordinates in the mesh.
+///
+/// The voxels can be modified with [`VoxModel::edit`], after which the `#model{no}` mesh and its sub-meshes (including the sub-meshes of chunks and the copies of mirrored models)
+/// are updated in place, only meshing again the chunks of the model around the modified voxels.
+/// Models which aren't split into chunks are meshed whole when loaded, and in chunks of 32 voxels from their first edit on,
+/// so greedy quads no longer span the borders of these chunks once a model is edited.
+/// Models split into chunks only update the meshes of their chunks, and the meshes of the whole model and of each material slot
+/// once an entity shows them.
+#[derive(Clone, TypeUuid)]
+#[uuid = "b6a5a1d2-6f0e-4f57-8d0b-3c5b8e0c9a42"]
+pub struct VoxModel {
+    size: UVec3,
+    voxels: Vec<u8>,
+    origin: Vec3,
+    voxel_size: f32,
+    palette: Handle<VoxPalette>,
+    axis_convention: AxisConvention,
+    pub(crate) mesher: Option<Box<ModelMesher>>,
+}
+
+/// An edit of the voxels of a [`VoxModel`], in grid positions.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VoxelEdit {
+    /// Sets the palette index of a voxel.
+    Set { position: UVec3, index: u8 },
+    /// Empties a voxel.
+    Clear { position: UVec3 },
+    /// Sets the palette index of the voxels between two corners, both included, or empties them if `index` is `None`.
+    FillBox {
+        min: UVec3,
+        max: UVec3,
+        index: Option<u8>,
+    },
+    /// Sets the palette index of the voxels with their center inside a sphere, or empties them if `index` is `None`.
+    /// The center and the radius are in voxels, with the grid position `(0, 0, 0)` spanning from `(0.0, 0.0, 0.0)` to `(1.0, 1.0, 1.0)`.
+    Sphere {
+        center: Vec3,
+        radius: f32,
+        index: Option<u8>,
+    },
+}
+
+impl VoxModel {
+    /// Converts the voxels of a model of a `.vox` file, which must be within its size as checked by [`check_models`].
+    pub(crate) fn from_model(
+        model: &Model,
+        origin: Vec3,
+        voxel_size: f32,
+        palette: Handle<VoxPalette>,
+        axis_convention: AxisConvention,
+    ) -> Self {
+        let magica_size = [model.size.x, model.size.y, model.size.z];
+        let mut voxels = Self::empty(magica_size, origin, voxel_size, palette, axis_convention);
+        for voxel in model.voxels.iter() {
+            let position = [voxel.x, voxel.y, voxel.z].map(u32::from);
+            voxels.set_magica(position, voxel.i);
+        }
+        voxels
+    }
+
+    fn empty(
+        magica_size: [u32; 3],
+        origin: Vec3,
+        voxel_size: f32,
+        palette: Handle<VoxPalette>,
+        axis_convention: AxisConvention,
+    ) -> Self {
+        let size = axis_convention.grid_size(magica_size);
+        Self {
+            size,
+            voxels: vec![EMPTY; (size.x * size.y * size.z) as usize],
+            origin,
+            voxel_size,
+            palette,
+            axis_convention,
+            mesher: None,
+        }
+    }
+
+    /// Returns a copy of the model with blocks of `factor`³ voxels merged into single voxels.
+    ///
+    /// A block is filled if at least half of its voxels are, with the most common palette index of the block.
+    /// Blocks are aligned on MagicaVoxel's axes, so that the downsampled model keeps the same MagicaVoxel pivot.
+    pub(crate) fn downsampled(&self, factor: u32) -> VoxModel {
+        let magica_size = self.magica_size();
+        let mut downsampled = Self::empty(
+            magica_size.map(|size| (size + factor - 1) / factor),
+            self.origin,
+            self.voxel_size * factor as f32,
+            self.palette.clone(),
+            self.axis_convention,
+        );
+        let block_size = downsampled.magica_size();
+
+        let mut counts = [0u32; 256];
+        for block_z in 0..block_size[2] {
+            for block_y in 0..block_size[1] {
+                for block_x in 0..block_size[0] {
+                    let block = [block_x, block_y, block_z];
+                    let min = block.map(|p| p * factor);
+                    let max = [0, 1, 2].map(|axis| (min[axis] + factor).min(magica_size[axis]));
+
+                    counts.fill(0);
+                    for z in min[2]..max[2] {
+                        for y in min[1]..max[1] {
+                            for x in min[0]..max[0] {
+                                let index = self.get_magica([x, y, z]).unwrap_or(EMPTY);
+                                counts[index as usize] += 1;
+                            }
+                        }
+                    }
+
+                    let total: u32 = [0, 1, 2].map(|axis| max[axis] - min[axis]).iter().product();
+                    if (total - counts[EMPTY as usize]) * 2 >= total {
+                        let index = (0..EMPTY)
+                            .max_by_key(|index| counts[*index as usize])
+                            .unwrap_or_default();
+                        downsampled.set_magica(block, index);
+                    }
+                }
+            }
+        }
+
+        downsampled
+    }
+
+    /// The size of the model in MagicaVoxel's axes.
+    pub(crate) fn magica_size(&self) -> [u32; 3] {
+        let mut size = [0; 3];
+        for (i, (axis, _)) in self.axis_convention.axes().into_iter().enumerate() {
+            size[axis] = self.size[i];
+        }
+        size
+    }
+
+    /// Returns the palette index of the voxel at the given position in MagicaVoxel's axes, or `None` if it's empty or outside of the model.
+    pub(crate) fn get_magica(&self, position: [u32; 3]) -> Option<u8> {
+        let size = self.magica_size();
+        if (0..3).any(|axis| position[axis] >= size[axis]) {
+            return None;
+        }
+        let position = self.axis_convention.grid_position(position, size);
+        self.get(position.x, position.y, position.z)
+    }
+
+    fn set_magica(&mut self, position: [u32; 3], index: u8) {
+        let position = self
+            .axis_convention
+            .grid_position(position, self.magica_size());
+        self.voxels[linearize(self.size, position)] = index;
+    }
+
+    /// Returns the position in MagicaVoxel's axes of the voxel at the given grid position.
+    pub(crate) fn magica_position(&self, position: UVec3) -> [u32; 3] {
+        self.axis_convention.magica_position(position, self.size)
+    }
+
+    /// The number of voxels of the grid along each axis.
+    pub fn size(&self) -> UVec3 {
+        self.size
+    }
+
+    /// The position of the corner of the grid with the lowest coordinates, in the space of the meshes of the model.
+    pub fn origin(&self) -> Vec3 {
+        self.origin
+    }
+
+    /// The size of a voxel in world units.
+    pub fn voxel_size(&self) -> f32 {
+        self.voxel_size
+    }
+
+    /// The axes of the grid.
+    pub fn axis_convention(&self) -> AxisConvention {
+        self.axis_convention
+    }
+
+    /// The palette the indices of the voxels refer to.
+    pub fn palette(&self) -> &Handle<VoxPalette> {
+        &self.palette
+    }
+
+    /// Returns the palette index of the voxel at the given grid position, or `None` if it's empty or outside of the grid.
+    pub fn get(&self, x: u32, y: u32, z: u32) -> Option<u8> {
+        let position = UVec3::new(x, y, z);
+        if position.cmpge(self.size).any() {
+            return None;
+        }
+        match self.voxels[linearize(self.size, position)] {
+            EMPTY => None,
+            index => Some(index),
+        }
+    }
+
+    /// Iterates over the grid positions and palette indices of the voxels which aren't empty.
+    pub fn iter(&self) -> impl Iterator<Item = (UVec3, u8)> + '_ {
+        let size = self.size;
+        self.voxels
+            .iter()
+            .enumerate()
+            .filter(|(_, index)| **index != EMPTY)
+            .map(move |(i, index)| (delinearize(size, i), *index))
+    }
+
+    /// Returns the position of the center of a voxel in the space of the meshes of the model.
+    pub fn voxel_center(&self, position: UVec3) -> Vec3 {
+        self.origin + (position.as_vec3() + 0.5) * self.voxel_size
+    }
+
+    /// Returns the grid position of the voxel containing a point in the space of the meshes of the model,
+    /// or `None` if the point is outside of the grid.
+    pub fn voxel_at(&self, point: Vec3) -> Option<UVec3> {
+        let position = ((point - self.origin) / self.voxel_size).floor();
+        if position.cmplt(Vec3::ZERO).any() || position.cmpge(self.size.as_vec3()).any() {
+            return None;
+        }
+        Some(position.as_uvec3())
+    }
+
+    /// Applies an edit to the voxels, and marks the chunks around the modified voxels for remeshing.
+    ///
+    /// Positions outside of the grid are ignored.
+    pub fn edit(&mut self, edit: VoxelEdit) {
+        let (min, max, index) = match edit {
+            VoxelEdit::Set { position, index } => (position, position, Some(index)),
+            VoxelEdit::Clear { position } => (position, position, None),
+            VoxelEdit::FillBox { min, max, index } => (min, max, index),
+            VoxelEdit::Sphere {
+                center,
+                radius,
+                index,
+            } => (
+                (center - radius).max(Vec3::ZERO).floor().as_uvec3(),
+                (center + radius).max(Vec3::ZERO).floor().as_uvec3(),
+                index,
+            ),
+        };
+        if self.size.cmpeq(UVec3::ZERO).any() || min.cmpge(self.size).any() || min.cmpgt(max).any()
+        {
+            return;
+        }
+        let max = max.min(self.size - 1);
+
+        for z in min.z..=max.z {
+            for y in min.y..=max.y {
+                for x in min.x..=max.x {
+                    let position = UVec3::new(x, y, z);
+                    if let VoxelEdit::Sphere { center, radius, .. } = edit {
+                        if (position.as_vec3() + 0.5).distance_squared(center) > radius * radius {
+                            continue;
+                        }
+                    }
+                    self.voxels[linearize(self.size, position)] = index.unwrap_or(EMPTY);
+                }
+            }
+        }
+
+        if let Some(mut mesher) = self.mesher.take() {
+            mesher.mark_dirty(self, min, max);
+            self.mesher = Some(mesher);
+        }
+    }
+}
+
+/// Checks that the models of a `.vox` file fit in MagicaVoxel's maximum size, and that their voxels are within their size.
+pub(crate) fn check_models(models: &[Model]) -> Result<(), VoxLoadError> {
+    for (index, model) in models.iter().enumerate() {
+        let size = [model.size.x, model.size.y, model.size.z];
+        if size.iter().any(|axis| *axis > MAX_MODEL_SIZE) {
+            return Err(VoxLoadError::ModelTooLarge { model: index, size });
+        }
+        let outside = model.voxels.iter().find(|voxel| {
+            [voxel.x, voxel.y, voxel.z]
+                .into_iter()
+                .zip(size)
+                .any(|(position, size)| u32::from(position) >= size)
+        });
+        if let Some(voxel) = outside {
+            return Err(VoxLoadError::VoxelOutOfBounds {
+                model: index,
+                position: [voxel.x, voxel.y, voxel.z],
+                size,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+fn linearize(size: UVec3, position: UVec3) -> usize {
+    (position.x + size.x * (position.y + size.y * position.z)) as usize
+}
+
+fn delinearize(size: UVec3, index: usize) -> UVec3 {
+    let index = index as u32;
+    UVec3::new(
+        index % size.x,
+        (index / size.x) % size.y,
+        index / (size.x * size.y),
+    )
+}
+
+/// The components holding the meshes shown by entities, to find out whether the meshes of a whole model are used.
+#[derive(SystemParam)]
+pub(crate) struct MeshUsers<'w, 's> {
+    meshes: Query<'w, 's, &'static Handle<Mesh>>,
+    flipbooks: Query<'w, 's, &'static VoxFlipbook>,
+    lods: Query<'w, 's, &'static VoxLod>,
+    swapped: Query<'w, 's, &'static PaletteSwapped>,
+}
+
+impl MeshUsers<'_, '_> {
+    /// Returns which of the given meshes are shown by an entity, or may be shown by a flipbook, a level of detail or a palette swap.
+    fn used(&self, meshes: &HashSet<Handle<Mesh>>) -> HashSet<Handle<Mesh>> {
+        self.meshes
+            .iter()
+            .chain(self.flipbooks.iter().flat_map(|flipbook| &flipbook.frames))
+            .chain(self.lods.iter().flat_map(|lod| &lod.meshes))
+            .chain(self.swapped.iter().flat_map(|swapped| &swapped.sources))
+            .filter(|mesh| meshes.contains(*mesh))
+            .map(Handle::clone_weak)
+            .collect()
+    }
+}
+
+/// Updates the meshes of the [`VoxModel`]s which were edited.
+///
+/// Only the chunks around the edited voxels are meshed again. The meshes of the whole model and of each material slot are made of
+/// all the chunks, so for models split into chunks, whose scene only uses the meshes of the chunks,
+/// they're only generated again once an entity uses them.
+/// When edits put voxels in a chunk for a material slot it had no voxels for, the sub-mesh of the pair is added
+/// and spawned under the nodes of the model.
+pub(crate) fn remesh_edited_models(
+    mut events: EventReader<AssetEvent<VoxModel>>,
+    mut models: ResMut<Assets<VoxModel>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut commands: Commands,
+    shapes: Query<(Entity, &ChunkedShape, Option<&VoxLayer>)>,
+    users: MeshUsers,
+    mut stale: Local<HashSet<Handle<VoxModel>>>,
+) {
+    for event in events.iter() {
+        let AssetEvent::Modified { handle } = event else {
+            continue;
+        };
+        // remeshing modifies the model again, which is ignored as it's no longer dirty.
+        let dirty = models
+            .get(handle)
+            .and_then(|model| model.mesher.as_ref())
+            .map_or(false, |mesher| mesher.is_dirty());
+        if !dirty {
+            continue;
+        }
+
+        let Some(model) = models.get_mut(handle) else {
+            continue;
+        };
+        let Some(mut mesher) = model.mesher.take() else {
+            continue;
+        };
+
+        let mut added = Vec::new();
+        for (chunk, parts) in mesher.mesh_chunks(model) {
+            for (slot, part) in parts.into_iter().enumerate() {
+                let Some(chunk_part) = mesher
+                    .chunk_parts
+                    .get(chunk)
+                    .and_then(|parts| parts.get(slot))
+                else {
+                    continue;
+                };
+                let mirrored = mesher
+                    .mirrored_chunk_parts
+                    .get(chunk)
+                    .and_then(|parts| parts.get(slot))
+                    .cloned()
+                    .flatten();
+                match (chunk_part.clone(), part) {
+                    // a material which is no longer used in the chunk leaves an empty sub-mesh.
+                    (Some(mesh), part) => update_mesh(
+                        &mut meshes,
+                        &mesh,
+                        mirrored.as_ref(),
+                        part.unwrap_or_else(empty_mesh),
+                    ),
+                    // the chunk had no voxels using the slot, so its sub-mesh is added and spawned now.
+                    (None, Some(part)) => {
+                        let mirrored = mesher
+                            .is_mirrored()
+                            .then(|| meshes.add(reversed_winding(&part)));
+                        let mesh = meshes.add(part);
+                        mesher.chunk_parts[chunk][slot] = Some(mesh.clone());
+                        if let Some(parts) = mesher.mirrored_chunk_parts.get_mut(chunk) {
+                            parts[slot] = mirrored.clone();
+                        }
+                        added.push((chunk, slot, mesh, mirrored));
+                    }
+                    (None, None) => {}
+                }
+            }
+        }
+        for (node, shape, layer) in shapes
+            .iter()
+            .filter(|(_, shape, _)| shape.voxels == *handle)
+        {
+            for (chunk, slot, mesh, mirrored) in &added {
+                let mesh = match (shape.mirrored, mirrored) {
+                    (true, Some(mirrored)) => mirrored,
+                    _ => mesh,
+                };
+                commands.entity(node).with_children(|children| {
+                    let mut entity = children.spawn(part_bundle(
+                        mesh.clone(),
+                        mesher.slot_materials[*slot].clone(),
+                        shape.translation,
+                        format!("chunk{chunk}/{}", mesher.slot_label(*slot)),
+                    ));
+                    if let Some(layer) = layer {
+                        entity.insert(layer.clone());
+                    }
+                });
+            }
+        }
+        match mesher.is_split() {
+            true => {
+                stale.insert(handle.clone_weak());
+            }
+            false => update_whole_meshes(&mut mesher, &mut meshes),
+        }
+
+        model.mesher = Some(mesher);
+    }
+
+    if stale.is_empty() {
+        return;
+    }
+    let whole_meshes: HashSet<Handle<Mesh>> = stale
+        .iter()
+        .filter_map(|handle| models.get(handle)?.mesher.as_ref())
+        .flat_map(|mesher| mesher.whole_meshes().map(Handle::clone_weak))
+        .collect();
+    let used = users.used(&whole_meshes);
+    stale.retain(|handle| {
+        let in_use = match models.get(handle).and_then(|model| model.mesher.as_ref()) {
+            Some(mesher) if mesher.is_stale() => {
+                mesher.whole_meshes().any(|mesh| used.contains(mesh))
+            }
+            _ => return false,
+        };
+        if !in_use {
+            return true;
+        }
+        if let Some(model) = models.get_mut(handle) {
+            if let Some(mut mesher) = model.mesher.take() {
+                update_whole_meshes(&mut mesher, &mut meshes);
+                model.mesher = Some(mesher);
+            }
+        }
+        false
+    });
+}
+
+/// Generates the meshes of the whole model and of each material slot again, and updates them in place.
+fn update_whole_meshes(mesher: &mut ModelMesher, meshes: &mut Assets<Mesh>) {
+    let model_meshes = mesher.mesh_whole();
+    for (i, handle) in mesher.meshes.iter().enumerate() {
+        update_mesh(
+            meshes,
+            handle,
+            mesher.mirrored_meshes.get(i),
+            model_meshes.mesh.clone(),
+        );
+    }
+    for (slot, (handle, part)) in mesher.parts.iter().zip(model_meshes.parts).enumerate() {
+        // a material which is no longer used leaves an empty sub-mesh.
+        let part = part.unwrap_or_else(empty_mesh);
+        update_mesh(meshes, handle, mesher.mirrored_parts.get(slot), part);
+    }
+}
+
+/// Replaces a mesh in place, along with its copy with reversed winding if the model is spawned mirrored.
+fn update_mesh(
+    meshes: &mut Assets<Mesh>,
+    handle: &Handle<Mesh>,
+    mirrored: Option<&Handle<Mesh>>,
+    new_mesh: Mesh,
+) {
+    if let Some(mesh) = mirrored.and_then(|handle| meshes.get_mut(handle)) {
+        *mesh = reversed_winding(&new_mesh);
+    }
+    if let Some(mesh) = meshes.get_mut(handle) {
+        *mesh = new_mesh;
+    }
+}
+
+/// Removes the bounding box of the entities whose mesh was modified, such as by [`remesh_edited_models`].
+///
+/// Bevy only computes the [`Aabb`] of entities which don't have one, so the box of a model would otherwise keep its size
+/// from when it was spawned, and the voxels added outside of it would be culled.
+pub(crate) fn reset_modified_bounds(
+    mut commands: Commands,
+    mut events: EventReader<AssetEvent<Mesh>>,
+    entities: Query<(Entity, &Handle<Mesh>), With<Aabb>>,
+) {
+    let modified: HashSet<Handle<Mesh>> = events
+        .iter()
+        .filter_map(|event| match event {
+            AssetEvent::Modified { handle } => Some(handle.clone_weak()),
+            _ => None,
+        })
+        .collect();
+    if modified.is_empty() {
+        return;
+    }
+
+    for (entity, mesh) in entities.iter() {
+        if modified.contains(mesh) {
+            commands.entity(entity).remove::<Aabb>();
+        }
+    }
+}
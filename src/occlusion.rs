@@ -0,0 +1,169 @@
+use block_mesh::{MergeVoxel, Voxel as BlockyVoxel, VoxelVisibility};
+use ndshape::{RuntimeShape, Shape};
+
+use crate::voxel::Voxel;
+
+/// The brightness of a vertex for each of its ambient occlusion values, from fully occluded to unoccluded.
+const OCCLUSION_CURVE: [f32; 4] = [0.5, 0.7, 0.85, 1.0];
+
+/// A voxel along with the ambient occlusion of the corners of its faces.
+///
+/// Merging voxels with different occlusion values would smear the occlusion over the merged quad,
+/// so the occlusion values are part of the merge value.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) struct OccludedVoxel(Voxel, u64);
+
+impl BlockyVoxel for OccludedVoxel {
+    fn get_visibility(&self) -> VoxelVisibility {
+        self.0.get_visibility()
+    }
+}
+
+impl MergeVoxel for OccludedVoxel {
+    type MergeValue = OccludedVoxel;
+
+    fn merge_value(&self) -> Self::MergeValue {
+        *self
+    }
+}
+
+/// Computes the ambient occlusion of every face corner of the voxels in the buffer.
+pub(crate) fn occlude_voxels(
+    buffer_shape: &RuntimeShape<u32, 3>,
+    buffer: &[Voxel],
+) -> Vec<OccludedVoxel> {
+    let size = buffer_shape.as_array();
+
+    buffer
+        .iter()
+        .enumerate()
+        .map(|(index, voxel)| {
+            let position = buffer_shape.delinearize(index as u32);
+            if voxel.1 == VoxelVisibility::Empty
+                || position
+                    .iter()
+                    .zip(size)
+                    .any(|(p, s)| *p == 0 || *p == s - 1)
+            {
+                // padding voxels are never meshed.
+                return OccludedVoxel(*voxel, 0);
+            }
+
+            let mut occlusion = 0u64;
+            for axis in 0..3 {
+                for sign in [-1, 1] {
+                    let corners = face_corners(position, axis, sign);
+                    for corner in corners {
+                        let value =
+                            vertex_occlusion(buffer_shape, buffer, position, corner, axis, sign);
+                        occlusion = (occlusion << 2) | value as u64;
+                    }
+                }
+            }
+
+            OccludedVoxel(*voxel, occlusion)
+        })
+        .collect()
+}
+
+/// Returns the ambient occlusion values of the corners of a quad, given its positions and normal in buffer coordinates.
+pub(crate) fn quad_occlusion(
+    buffer_shape: &RuntimeShape<u32, 3>,
+    buffer: &[Voxel],
+    positions: &[[f32; 3]; 4],
+    normal: [f32; 3],
+) -> [u8; 4] {
+    let axis = normal.iter().position(|n| *n != 0.0).unwrap_or(0);
+    let sign = if normal[axis] > 0.0 { 1 } else { -1 };
+
+    let center = positions.iter().fold([0.0; 3], |center, position| {
+        [
+            center[0] + position[0] / 4.0,
+            center[1] + position[1] / 4.0,
+            center[2] + position[2] / 4.0,
+        ]
+    });
+
+    positions.map(|position| {
+        let corner = position.map(|x| x as u32);
+        // the voxel of the quad touching this corner
+        let mut voxel = corner;
+        for (i, coordinate) in voxel.iter_mut().enumerate() {
+            let before_corner = if i == axis {
+                sign > 0
+            } else {
+                position[i] > center[i]
+            };
+            if before_corner {
+                *coordinate -= 1;
+            }
+        }
+        vertex_occlusion(buffer_shape, buffer, voxel, corner, axis, sign)
+    })
+}
+
+/// Returns the brightness of a vertex with the given ambient occlusion value.
+pub(crate) fn occlusion_factor(occlusion: u8) -> f32 {
+    OCCLUSION_CURVE[occlusion as usize]
+}
+
+/// Whether the diagonal of a quad should be flipped so that the occlusion is interpolated isotropically.
+///
+/// The default triangulation of a quad splits it between its second and third corners.
+pub(crate) fn flip_diagonal(occlusion: [u8; 4]) -> bool {
+    occlusion[0] as u32 + occlusion[3] as u32 > occlusion[1] as u32 + occlusion[2] as u32
+}
+
+/// Returns the corners of the face of a voxel, in buffer coordinates.
+fn face_corners(voxel: [u32; 3], axis: usize, sign: i32) -> [[u32; 3]; 4] {
+    let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+    let mut corners = [voxel; 4];
+    for (i, corner) in corners.iter_mut().enumerate() {
+        if sign > 0 {
+            corner[axis] += 1;
+        }
+        corner[u] += i as u32 & 1;
+        corner[v] += i as u32 >> 1;
+    }
+    corners
+}
+
+/// Computes the classic ambient occlusion value of a face corner, from 0 (fully occluded) to 3 (unoccluded),
+/// by looking at the three voxels touching the corner in front of the face.
+fn vertex_occlusion(
+    buffer_shape: &RuntimeShape<u32, 3>,
+    buffer: &[Voxel],
+    voxel: [u32; 3],
+    corner: [u32; 3],
+    axis: usize,
+    sign: i32,
+) -> u8 {
+    let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+
+    let mut front = voxel;
+    front[axis] = (front[axis] as i32 + sign) as u32;
+
+    // the neighbour of the front voxel towards the corner along the given axis
+    let outwards = |mut position: [u32; 3], axis: usize| {
+        position[axis] = if corner[axis] > voxel[axis] {
+            voxel[axis] + 1
+        } else {
+            voxel[axis] - 1
+        };
+        position
+    };
+
+    let solid = |position: [u32; 3]| {
+        (buffer[buffer_shape.linearize(position) as usize].1 != VoxelVisibility::Empty) as u8
+    };
+
+    let side1 = solid(outwards(front, u));
+    let side2 = solid(outwards(front, v));
+    let corner = solid(outwards(outwards(front, u), v));
+
+    if side1 == 1 && side2 == 1 {
+        0
+    } else {
+        3 - (side1 + side2 + corner)
+    }
+}
@@ -0,0 +1,335 @@
+use bevy::{
+    prelude::*,
+    reflect::TypeUuid,
+    render::{
+        mesh::VertexAttributeValues,
+        primitives::Aabb,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+        texture::ImageSampler,
+    },
+    utils::{HashMap, HashSet},
+};
+
+use crate::{
+    mesh::{ATTRIBUTE_OCCLUSION, ATTRIBUTE_PALETTE_INDEX},
+    VoxFlipbook, VoxLod,
+};
+
+/// Creates a texture with one texel per palette color, in a single row.
+///
+/// If `linear` is set, the palette colors are in linear RGB, and are stored in an sRGB texture so that they're sampled back unchanged.
+pub(crate) fn palette_image(palette: &[[f32; 4]], linear: bool) -> Image {
+    let data = palette
+        .iter()
+        .flat_map(|color| {
+            let color = match linear {
+                true => Color::rgba_linear(color[0], color[1], color[2], color[3]),
+                false => Color::rgba(color[0], color[1], color[2], color[3]),
+            };
+            color
+                .as_rgba_f32()
+                .map(|channel| (channel.clamp(0.0, 1.0) * u8::MAX as f32).round() as u8)
+        })
+        .collect();
+
+    let mut image = Image::new(
+        Extent3d {
+            width: palette.len() as u32,
+            height: 1,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        match linear {
+            true => TextureFormat::Rgba8UnormSrgb,
+            false => TextureFormat::Rgba8Unorm,
+        },
+    );
+    // palette colors must not bleed into each other.
+    image.sampler_descriptor = ImageSampler::nearest();
+    image
+}
+
+/// Returns the UV coordinates of the center of a palette entry in the palette texture.
+pub(crate) fn palette_uv(index: u8, palette_len: usize) -> [f32; 2] {
+    [(index as f32 + 0.5) / palette_len as f32, 0.5]
+}
+
+/// The colors of the palette of a `.vox` file, labeled `#palette/colors`.
+///
+/// A modified copy can be applied to spawned models with a [`VoxPaletteSwap`].
+#[derive(Clone, Debug, TypeUuid)]
+#[uuid = "6c1c2fcb-2d0c-4a85-9e3f-5f4e1b07a9d1"]
+pub struct VoxPalette {
+    /// The colors of the palette entries, as they are written in the vertex colors of the meshes.
+    /// They include the opacity of glass materials.
+    pub colors: Vec<[f32; 4]>,
+    /// Whether the colors are in linear RGB, see [`crate::VoxLoaderSettings::convert_rgb_to_linear`].
+    pub linear: bool,
+}
+
+impl VoxPalette {
+    /// Replaces the color of a palette entry, converting it to the color space of the palette.
+    pub fn set_color(&mut self, index: u8, color: Color) {
+        if let Some(entry) = self.colors.get_mut(index as usize) {
+            *entry = match self.linear {
+                true => color.as_linear_rgba_f32(),
+                false => color.as_rgba_f32(),
+            };
+        }
+    }
+
+    /// Returns the color of a palette entry after remapping its index.
+    fn color(&self, index: u8, remap: &HashMap<u8, u8>) -> [f32; 4] {
+        let index = remap.get(&index).copied().unwrap_or(index);
+        self.colors.get(index as usize).copied().unwrap_or([1.0; 4])
+    }
+}
+
+/// Recolors the meshes of an entity and of its descendants with another palette, without reloading the `.vox` file.
+///
+/// Meshes using vertex colors are copied and recolored, keeping their ambient occlusion,
+/// while materials sampling the palette texture are copied with a new palette texture.
+/// Every recolored entity gets its own copy, including every frame of a [`VoxFlipbook`] and every level of a [`VoxLod`],
+/// and is recolored again whenever the swap or its palette changes, or when the meshes it was copied from are modified,
+/// such as by [`crate::VoxModel::edit`].
+#[derive(Component, Clone, Debug)]
+pub struct VoxPaletteSwap {
+    /// The palette replacing the one the meshes were generated with.
+    pub palette: Handle<VoxPalette>,
+    /// Palette indices which take the color of another index of the palette.
+    pub remap: HashMap<u8, u8>,
+}
+
+impl VoxPaletteSwap {
+    /// Creates a [`VoxPaletteSwap`] replacing the palette of the meshes.
+    pub fn new(palette: Handle<VoxPalette>) -> Self {
+        Self {
+            palette,
+            remap: HashMap::default(),
+        }
+    }
+
+    /// Makes the voxels with the palette index `from` take the color of the palette index `to`.
+    pub fn with_remap(mut self, from: u8, to: u8) -> Self {
+        self.remap.insert(from, to);
+        self
+    }
+}
+
+/// Marks the entities recolored by a [`VoxPaletteSwap`], so that they're only recolored again when it changes
+/// or when the meshes they were copied from are modified.
+#[derive(Component)]
+pub(crate) struct PaletteSwapped {
+    /// The meshes the recolored copies were made from, in the order of the frames of a [`VoxFlipbook`] or the levels of a [`VoxLod`],
+    /// empty if the material was recolored instead of the meshes.
+    pub(crate) sources: Vec<Handle<Mesh>>,
+}
+
+/// Recolors the descendants of palette swaps, only walking a subtree when the swap or its palette changed,
+/// when entities were added to it or had their source meshes modified, or when some of its meshes or materials weren't loaded yet.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn apply_palette_swaps(
+    mut commands: Commands,
+    mut palette_events: EventReader<AssetEvent<VoxPalette>>,
+    mut mesh_events: EventReader<AssetEvent<Mesh>>,
+    swaps: Query<(Entity, &VoxPaletteSwap, ChangeTrackers<VoxPaletteSwap>)>,
+    changed_children: Query<Entity, Changed<Children>>,
+    parents: Query<&Parent>,
+    children: Query<&Children>,
+    swapped: Query<(Entity, &PaletteSwapped)>,
+    mut targets: Query<(
+        &mut Handle<Mesh>,
+        &mut Handle<StandardMaterial>,
+        Option<&mut VoxFlipbook>,
+        Option<&mut VoxLod>,
+        Option<&PaletteSwapped>,
+    )>,
+    palettes: Res<Assets<VoxPalette>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut images: ResMut<Assets<Image>>,
+    mut pending: Local<HashSet<Entity>>,
+) {
+    let updated: Vec<Handle<VoxPalette>> = palette_events
+        .iter()
+        .filter_map(|event| match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+                Some(handle.clone_weak())
+            }
+            _ => None,
+        })
+        .collect();
+
+    let modified: HashSet<Handle<Mesh>> = mesh_events
+        .iter()
+        .filter_map(|event| match event {
+            AssetEvent::Modified { handle } => Some(handle.clone_weak()),
+            _ => None,
+        })
+        .collect();
+    // the recolored entities whose source meshes were modified, such as by edits of their voxels.
+    let stale: HashSet<Entity> = match modified.is_empty() {
+        true => HashSet::default(),
+        false => swapped
+            .iter()
+            .filter(|(_, swapped)| swapped.sources.iter().any(|mesh| modified.contains(mesh)))
+            .map(|(entity, _)| entity)
+            .collect(),
+    };
+
+    // the entities whose descendants changed, which is how spawning scenes and stale copies show up.
+    let mut grown = HashSet::default();
+    for entity in changed_children.iter().chain(stale.iter().copied()) {
+        let mut ancestor = Some(entity);
+        while let Some(entity) = ancestor {
+            if !grown.insert(entity) {
+                break;
+            }
+            ancestor = parents.get(entity).ok().map(|parent| parent.get());
+        }
+    }
+
+    pending.retain(|root| swaps.contains(*root));
+
+    for (root, swap, tracker) in swaps.iter() {
+        let Some(palette) = palettes.get(&swap.palette) else {
+            continue;
+        };
+        let changed = tracker.is_changed() || updated.contains(&swap.palette);
+        if !changed && !grown.contains(&root) && !pending.remove(&root) {
+            continue;
+        }
+
+        let mut stack = vec![root];
+        while let Some(entity) = stack.pop() {
+            if let Ok(entity_children) = children.get(entity) {
+                stack.extend(entity_children.iter());
+            }
+
+            let Ok((mut mesh, mut material, flipbook, lod, swapped)) = targets.get_mut(entity)
+            else {
+                continue;
+            };
+            let is_stale = stale.contains(&entity);
+            if swapped.is_some() && !changed && !is_stale {
+                continue;
+            }
+
+            let recolored = match materials.get(&*material) {
+                Some(StandardMaterial {
+                    base_color_texture: Some(_),
+                    ..
+                }) => {
+                    let colors: Vec<[f32; 4]> = (0..palette.colors.len())
+                        .map(|index| palette.color(index as u8, &swap.remap))
+                        .collect();
+                    let new_material = StandardMaterial {
+                        base_color_texture: Some(
+                            images.add(palette_image(&colors, palette.linear)),
+                        ),
+                        ..materials.get(&*material).unwrap().clone()
+                    };
+                    *material = materials.add(new_material);
+                    Some(Vec::new())
+                }
+                Some(_) => {
+                    // flipbooks cycle through their frames and lods through their levels, so all of them are recolored,
+                    // from the meshes they were copied from if they were already recolored.
+                    let sources = match (swapped, &flipbook, &lod) {
+                        (Some(swapped), ..) if !swapped.sources.is_empty() => {
+                            swapped.sources.clone()
+                        }
+                        (_, Some(flipbook), _) => flipbook.frames.clone(),
+                        (_, _, Some(lod)) => lod.meshes.clone(),
+                        _ => vec![mesh.clone()],
+                    };
+                    let new_meshes: Option<Vec<Option<Mesh>>> = sources
+                        .iter()
+                        .map(|source| {
+                            let source = meshes.get(source)?;
+                            Some(recolor_mesh(source, palette, &swap.remap))
+                        })
+                        .collect();
+                    match new_meshes {
+                        Some(new_meshes) if new_meshes.iter().all(Option::is_some) => {
+                            let new_handles: Vec<Handle<Mesh>> = new_meshes
+                                .into_iter()
+                                .flatten()
+                                .map(|new_mesh| meshes.add(new_mesh))
+                                .collect();
+                            match (flipbook, lod) {
+                                (Some(mut flipbook), _) => {
+                                    if let Some(frame) = new_handles.get(flipbook.frame()) {
+                                        *mesh = frame.clone();
+                                    }
+                                    flipbook.frames = new_handles;
+                                }
+                                (None, Some(mut lod)) => {
+                                    let level =
+                                        lod.meshes.iter().position(|lod_mesh| *lod_mesh == *mesh);
+                                    if let Some(lod_mesh) = new_handles.get(level.unwrap_or(0)) {
+                                        *mesh = lod_mesh.clone();
+                                    }
+                                    lod.meshes = new_handles;
+                                }
+                                (None, None) => *mesh = new_handles[0].clone(),
+                            }
+                            // the copies of modified meshes may not fit in the bounding box of the previous copies.
+                            if is_stale {
+                                commands.entity(entity).remove::<Aabb>();
+                            }
+                            Some(sources)
+                        }
+                        Some(_) => None,
+                        None => {
+                            pending.insert(root);
+                            None
+                        }
+                    }
+                }
+                None => {
+                    pending.insert(root);
+                    None
+                }
+            };
+
+            if let Some(sources) = recolored {
+                commands.entity(entity).insert(PaletteSwapped { sources });
+            }
+        }
+    }
+}
+
+/// Returns a copy of a mesh with its vertex colors taken from another palette, or `None` if it wasn't generated from a `.vox` file.
+fn recolor_mesh(mesh: &Mesh, palette: &VoxPalette, remap: &HashMap<u8, u8>) -> Option<Mesh> {
+    let Some(VertexAttributeValues::Uint32(indices)) = mesh.attribute(ATTRIBUTE_PALETTE_INDEX)
+    else {
+        return None;
+    };
+    let Some(VertexAttributeValues::Float32(occlusion)) = mesh.attribute(ATTRIBUTE_OCCLUSION)
+    else {
+        return None;
+    };
+
+    let colors = indices
+        .iter()
+        .zip(occlusion)
+        .map(|(index, occlusion)| {
+            let color = palette.color(*index as u8, remap);
+            [
+                color[0] * occlusion,
+                color[1] * occlusion,
+                color[2] * occlusion,
+                color[3],
+            ]
+        })
+        .collect();
+
+    let mut mesh = mesh.clone();
+    mesh.insert_attribute(
+        Mesh::ATTRIBUTE_COLOR,
+        VertexAttributeValues::Float32x4(colors),
+    );
+    Some(mesh)
+}
@@ -0,0 +1,131 @@
+use bevy::{
+    math::{IVec3, Ray, UVec3, Vec3},
+    prelude::GlobalTransform,
+};
+
+use crate::VoxModel;
+
+/// A voxel hit by a ray cast with [`VoxModel::raycast`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VoxelHit {
+    /// The grid position of the voxel.
+    pub position: UVec3,
+    /// The normal of the face of the voxel which was hit, in the grid axes, or zero if the ray started inside the voxel.
+    pub normal: IVec3,
+    /// The palette index of the voxel.
+    pub index: u8,
+    /// The world position where the ray entered the voxel.
+    pub point: Vec3,
+    /// The distance from the origin of the ray to the hit point, in world units.
+    pub distance: f32,
+}
+
+impl VoxModel {
+    /// Finds the first voxel hit by a world space ray, within a maximum distance in world units.
+    ///
+    /// `transform` is the transform of an entity with a mesh of the model, such as the entities of the spawned scene,
+    /// so that the ray is cast in the same space as the rendered voxels.
+    pub fn raycast(
+        &self,
+        transform: &GlobalTransform,
+        ray: Ray,
+        max_distance: f32,
+    ) -> Option<VoxelHit> {
+        let scale = ray.direction.length();
+        if scale == 0.0 || self.size().cmpeq(UVec3::ZERO).any() {
+            return None;
+        }
+
+        // the ray is moved into grid space, where voxels have a size of 1, keeping the parameter of its points.
+        let inverse = transform.affine().inverse();
+        let start = (inverse.transform_point3(ray.origin) - self.origin()) / self.voxel_size();
+        let direction = inverse.transform_vector3(ray.direction) / self.voxel_size();
+        let size = self.size().as_vec3();
+
+        // clips the ray against the bounds of the grid.
+        let mut enter = 0.0f32;
+        let mut exit = max_distance / scale;
+        let mut normal = IVec3::ZERO;
+        for axis in 0..3 {
+            if direction[axis] == 0.0 {
+                if start[axis] < 0.0 || start[axis] >= size[axis] {
+                    return None;
+                }
+                continue;
+            }
+            let near = (0.0 - start[axis]) / direction[axis];
+            let far = (size[axis] - start[axis]) / direction[axis];
+            let (near, far) = (near.min(far), near.max(far));
+            if near > enter {
+                enter = near;
+                normal = IVec3::ZERO;
+                normal[axis] = if direction[axis] > 0.0 { -1 } else { 1 };
+            }
+            exit = exit.min(far);
+        }
+        if enter > exit {
+            return None;
+        }
+
+        let step = IVec3::from(direction.to_array().map(|direction| {
+            if direction > 0.0 {
+                1
+            } else if direction < 0.0 {
+                -1
+            } else {
+                0
+            }
+        }));
+        let mut voxel = (start + direction * enter)
+            .floor()
+            .as_ivec3()
+            .clamp(IVec3::ZERO, self.size().as_ivec3() - 1);
+
+        // the ray parameters at which the ray crosses the next voxel boundary along each axis.
+        let mut next = Vec3::ZERO;
+        let mut delta = Vec3::ZERO;
+        for axis in 0..3 {
+            if step[axis] == 0 {
+                next[axis] = f32::INFINITY;
+                delta[axis] = f32::INFINITY;
+            } else {
+                let boundary = voxel[axis] + (step[axis] > 0) as i32;
+                next[axis] = (boundary as f32 - start[axis]) / direction[axis];
+                delta[axis] = 1.0 / direction[axis].abs();
+            }
+        }
+
+        let mut t = enter;
+        while t <= exit {
+            let position = voxel.as_uvec3();
+            if let Some(index) = self.get(position.x, position.y, position.z) {
+                let point = ray.origin + ray.direction * t;
+                return Some(VoxelHit {
+                    position,
+                    normal,
+                    index,
+                    point,
+                    distance: t * scale,
+                });
+            }
+
+            let axis = if next.x < next.y && next.x < next.z {
+                0
+            } else if next.y < next.z {
+                1
+            } else {
+                2
+            };
+            t = next[axis];
+            next[axis] += delta[axis];
+            voxel[axis] += step[axis];
+            normal = IVec3::ZERO;
+            normal[axis] = -step[axis];
+            if voxel[axis] < 0 || voxel[axis] >= self.size()[axis] as i32 {
+                return None;
+            }
+        }
+
+        None
+    }
+}
@@ -1,141 +1,658 @@
+use bevy::animation::{AnimationClip, AnimationPlayer, EntityPath, Keyframes, VariableCurve};
 use bevy::asset::{Handle, LoadContext, LoadedAsset};
 use bevy::hierarchy::{BuildWorldChildren, WorldChildBuilder};
-use bevy::math::{Mat3, Quat, UVec3, Vec3, Vec4, Vec4Swizzles};
+use bevy::math::{Mat3, Quat, Vec3};
 use bevy::pbr::{PbrBundle, StandardMaterial};
-use bevy::prelude::{Mesh, SpatialBundle, Transform, World};
+use bevy::prelude::{
+    Component, Mesh, Name, ReflectComponent, SpatialBundle, Transform, Visibility, World,
+};
+use bevy::reflect::Reflect;
 use bevy::scene::Scene;
-use dot_vox::{Dict, Model, SceneNode};
+use bevy::utils::{HashMap, HashSet};
+use dot_vox::{Dict, Layer, Model, SceneNode, ShapeModel};
+
+use crate::{
+    flipbook::VoxFlipbook, layer::extract_layer, lod::lod_distances, AxisConvention, HiddenLayers,
+    PivotMode, VoxInstance, VoxInstances, VoxLayer, VoxLoadError, VoxLoaderSettings, VoxLod,
+    VoxModel, VoxSceneRoot,
+};
 
 // constants used in magicavoxel's scene graph dictionaries
 const NAME: &str = "_name";
+const FRAME: &str = "_f";
 const ROTATION: &str = "_r";
 const TRANSLATION: &str = "_t";
 
+/// The meshes spawned for a model.
+pub(crate) struct SpawnedModel {
+    /// The mesh of the whole model.
+    pub(crate) mesh: Handle<Mesh>,
+    /// The voxels of the model.
+    pub(crate) voxels: Handle<VoxModel>,
+    /// The sub-mesh of each material slot.
+    pub(crate) slots: Vec<SpawnedPart>,
+    /// The sub-mesh of each chunk and material slot with voxels, spawned instead of the sub-meshes of the slots if the model is split into chunks.
+    pub(crate) chunks: Option<Vec<SpawnedPart>>,
+    /// The meshes with reversed winding spawned for the model under a mirrored transform node, if it is mirrored.
+    pub(crate) mirrored: Option<Box<SpawnedModel>>,
+}
+
+/// A sub-mesh of a model, spawned as its own entity.
+pub(crate) struct SpawnedPart {
+    /// The name of the entity.
+    pub(crate) name: String,
+    /// The material slot of the sub-mesh.
+    pub(crate) slot: usize,
+    pub(crate) mesh: Handle<Mesh>,
+    /// The less detailed versions of the sub-mesh.
+    pub(crate) lods: Vec<Handle<Mesh>>,
+}
+
+/// Marks the entity of the transform node of a shape whose model is split into chunks,
+/// so that the sub-meshes of the chunks which edits put voxels in are spawned under it.
+///
+/// These entities aren't part of the [`crate::VoxSceneIndex`].
+#[derive(Component, Reflect, Clone, Debug, Default)]
+#[reflect(Component)]
+pub(crate) struct ChunkedShape {
+    /// The voxels of the model.
+    pub(crate) voxels: Handle<VoxModel>,
+    /// Whether the node is mirrored, so that the sub-meshes are spawned with reversed winding.
+    pub(crate) mirrored: bool,
+    /// The translation of the sub-meshes from the entity of the node.
+    pub(crate) translation: Vec3,
+}
+
+/// Returns the components of the entity of a sub-mesh of a model.
+pub(crate) fn part_bundle(
+    mesh: Handle<Mesh>,
+    material: Handle<StandardMaterial>,
+    translation: Vec3,
+    name: String,
+) -> (PbrBundle, Name) {
+    (
+        PbrBundle {
+            mesh,
+            material,
+            transform: Transform::from_translation(translation),
+            ..PbrBundle::default()
+        },
+        Name::new(name),
+    )
+}
+
 pub(crate) fn load_scene(
     ctx: &mut LoadContext,
-    material: Handle<StandardMaterial>,
+    materials: &[Handle<StandardMaterial>],
     models: &[Model],
-    meshes: &[Handle<Mesh>],
+    meshes: &[SpawnedModel],
     scene: &[SceneNode],
+    layers: &[Layer],
+    settings: &VoxLoaderSettings,
 ) {
     let mut world = World::default();
+    let mut instances = HashMap::default();
+    let mut node_paths = HashMap::default();
     if !scene.is_empty() {
-        world
-            .spawn(SpatialBundle::VISIBLE_IDENTITY)
-            .with_children(|builder| {
-                let root = &scene[0];
-                let transform = Transform::IDENTITY;
-                traverse_scene(
-                    ctx, builder, scene, root, transform, models, &material, meshes,
-                );
-            });
+        let root_name = Name::new(
+            ctx.path()
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        );
+        let mut builder = SceneBuilder {
+            ctx,
+            scene,
+            layers,
+            models,
+            materials,
+            meshes,
+            settings,
+            paths: HashSet::default(),
+            instances: HashMap::default(),
+            node_paths: HashMap::default(),
+            animation: AnimationClip::default(),
+        };
+
+        let mut root = world.spawn((
+            SpatialBundle::VISIBLE_IDENTITY,
+            root_name.clone(),
+            VoxSceneRoot,
+        ));
+        root.with_children(|children| match &scene[0] {
+            // the root entity stands for the root transform node, which MagicaVoxel leaves unnamed and untransformed.
+            SceneNode::Transform { child, .. } => {
+                builder.traverse(children, *child as usize, None, &mut vec![root_name], false);
+            }
+            _ => builder.traverse(children, 0, None, &mut vec![root_name], false),
+        });
+
+        if !builder.animation.curves().is_empty() {
+            let animation = builder
+                .ctx
+                .set_labeled_asset("animation", LoadedAsset::new(builder.animation));
+            let mut player = AnimationPlayer::default();
+            player.play(animation).repeat();
+            root.insert(player);
+        }
+        instances = builder.instances;
+        node_paths = builder.node_paths;
     }
+
+    // every instance shares the mesh of its model, which the scene places.
+    for (name, node, model) in extract_instances(scene) {
+        let Some(spawned) = meshes.get(model) else {
+            continue;
+        };
+        let instance = VoxInstance {
+            model,
+            mesh: ctx.get_handle(&spawned.mesh),
+            path: node_paths.remove(&node),
+        };
+        ctx.set_labeled_asset(&format!("instance-{name}"), LoadedAsset::new(instance));
+    }
+    ctx.set_labeled_asset("instances", LoadedAsset::new(VoxInstances::new(instances)));
     ctx.set_default_asset(LoadedAsset::new(Scene::new(world)));
 }
 
-fn traverse_scene(
-    ctx: &mut LoadContext,
-    builder: &mut WorldChildBuilder,
-    scene: &[SceneNode],
-    root: &SceneNode,
-    root_transform: Transform,
-    models: &[Model],
-    material: &Handle<StandardMaterial>,
-    meshes: &[Handle<Mesh>],
-) {
-    match root {
-        SceneNode::Transform { frames, child, .. } => {
-            if let Some(child_root) = scene.get(*child as usize) {
-                let this_transform = Transform {
-                    translation: extract_translation(frames).unwrap_or_default(),
-                    rotation: extract_rotation(frames).unwrap_or_default(),
-                    ..Transform::default()
+/// Spawns the nodes of the scene graph, and collects their animations.
+struct SceneBuilder<'a, 'b> {
+    ctx: &'a mut LoadContext<'b>,
+    scene: &'a [SceneNode],
+    layers: &'a [Layer],
+    models: &'a [Model],
+    materials: &'a [Handle<StandardMaterial>],
+    meshes: &'a [SpawnedModel],
+    settings: &'a VoxLoaderSettings,
+    /// The paths of the spawned transform nodes, which must be unique for their animations to target them.
+    paths: HashSet<Vec<Name>>,
+    /// The model of the entity of each shape, by path from the root.
+    instances: HashMap<String, usize>,
+    /// The path of the entity of each spawned transform node of a shape, by index of the node.
+    node_paths: HashMap<usize, String>,
+    animation: AnimationClip,
+}
+
+impl<'a, 'b> SceneBuilder<'a, 'b> {
+    /// Spawns a node of the scene graph, given the layer of its nearest ancestor transform node in a layer,
+    /// the names of the entities of its ancestor transform nodes, and whether they mirror it.
+    ///
+    /// Each transform node is spawned as an entity with its local transform, parent of the entities spawned for its child node.
+    fn traverse(
+        &mut self,
+        builder: &mut WorldChildBuilder,
+        node: usize,
+        layer: Option<u32>,
+        path: &mut Vec<Name>,
+        mirrored: bool,
+    ) {
+        let Some(root) = self.scene.get(node) else {
+            return;
+        };
+
+        match root {
+            SceneNode::Transform {
+                attributes,
+                frames,
+                child,
+            } => {
+                let layer = extract_layer(attributes).or(layer);
+                let (vox_layer, hidden) = match layer {
+                    Some(id) => {
+                        let (layer, hidden) = VoxLayer::new(self.layers, id);
+                        (Some(layer), hidden)
+                    }
+                    None => (None, false),
                 };
-                let transform = root_transform * this_transform;
+                if hidden && self.settings.hidden_layers == HiddenLayers::Skip {
+                    return;
+                }
 
-                traverse_scene(
-                    ctx, builder, scene, child_root, transform, models, material, meshes,
-                );
-            }
-        }
-        SceneNode::Group { children, .. } => {
-            for child in children {
-                if let Some(child_root) = scene.get(*child as usize) {
-                    traverse_scene(
-                        ctx,
-                        builder,
-                        scene,
-                        child_root,
-                        root_transform,
-                        models,
-                        material,
-                        meshes,
+                let default_name = match self.scene.get(*child as usize) {
+                    Some(SceneNode::Shape { .. }) => format!("shape{child}"),
+                    _ => format!("group{child}"),
+                };
+                let name =
+                    self.unique_name(extract_name(attributes).unwrap_or(default_name), node, path);
+                let settings = self.settings;
+                // the scene graph is read in voxel units, and only scaled once the translations are known.
+                let scaled = |transform: Transform| Transform {
+                    translation: transform.translation * settings.voxel_size,
+                    ..transform
+                };
+                let transform = frames.first().map_or(Transform::IDENTITY, |frame| {
+                    frame_transform(frame, settings.axis_convention)
+                });
+                let mirrored =
+                    mirrored != (transform.scale.x * transform.scale.y * transform.scale.z < 0.0);
+
+                let mut entity = builder.spawn((
+                    SpatialBundle {
+                        visibility: Visibility {
+                            is_visible: !hidden || settings.hidden_layers == HiddenLayers::Show,
+                        },
+                        ..SpatialBundle::from_transform(scaled(transform))
+                    },
+                    Name::new(name.clone()),
+                ));
+                if let Some(layer) = &vox_layer {
+                    entity.insert(layer.clone());
+                }
+
+                path.push(Name::new(name));
+                if frames.len() > 1 {
+                    let keyframes = animation_keyframes(frames, settings);
+                    let path = EntityPath {
+                        parts: path.clone(),
+                    };
+                    let timestamps: Vec<f32> = keyframes.iter().map(|(time, _)| *time).collect();
+                    self.animation.add_curve_to_path(
+                        path.clone(),
+                        VariableCurve {
+                            keyframe_timestamps: timestamps.clone(),
+                            keyframes: Keyframes::Translation(
+                                keyframes
+                                    .iter()
+                                    .map(|(_, transform)| scaled(*transform).translation)
+                                    .collect(),
+                            ),
+                        },
+                    );
+                    self.animation.add_curve_to_path(
+                        path.clone(),
+                        VariableCurve {
+                            keyframe_timestamps: timestamps.clone(),
+                            keyframes: Keyframes::Rotation(
+                                keyframes
+                                    .iter()
+                                    .map(|(_, transform)| transform.rotation)
+                                    .collect(),
+                            ),
+                        },
                     );
+                    // the winding of the meshes follows the first keyframe, so keyframes mirroring the node differently render inside out.
+                    if keyframes
+                        .iter()
+                        .any(|(_, keyframe)| keyframe.scale != transform.scale)
+                    {
+                        self.animation.add_curve_to_path(
+                            path,
+                            VariableCurve {
+                                keyframe_timestamps: timestamps,
+                                keyframes: Keyframes::Scale(
+                                    keyframes
+                                        .iter()
+                                        .map(|(_, transform)| transform.scale)
+                                        .collect(),
+                                ),
+                            },
+                        );
+                    }
+                }
+
+                if let Some(SceneNode::Shape {
+                    models: shape_models,
+                    ..
+                }) = self.scene.get(*child as usize)
+                {
+                    // shapes with several models are instances of their first model.
+                    if let Some(model) = shape_models.first() {
+                        let path: Vec<&str> = path[1..].iter().map(Name::as_str).collect();
+                        let path = path.join("/");
+                        self.instances.insert(path.clone(), model.model_id as usize);
+                        self.node_paths.insert(node, path);
+                    }
+                }
+
+                let mut chunked = None;
+                entity.with_children(|builder| match self.scene.get(*child as usize) {
+                    Some(SceneNode::Shape {
+                        models: shape_models,
+                        ..
+                    }) => match shape_models.as_slice() {
+                        [model] => {
+                            chunked = self.spawn_model(
+                                builder,
+                                model.model_id as usize,
+                                vox_layer.as_ref(),
+                                mirrored,
+                            )
+                        }
+                        // the models of a shape are the frames of a frame by frame animation.
+                        _ => {
+                            self.spawn_flipbook(builder, shape_models, vox_layer.as_ref(), mirrored)
+                        }
+                    },
+                    _ => self.traverse(builder, *child as usize, layer, path, mirrored),
+                });
+                if let Some(chunked) = chunked {
+                    entity.insert(chunked);
                 }
+                path.pop();
+            }
+            SceneNode::Group { children, .. } => {
+                for child in children {
+                    self.traverse(builder, *child as usize, layer, path, mirrored);
+                }
+            }
+            // shapes are spawned along with their parent transform node.
+            SceneNode::Shape { .. } => {}
+        }
+    }
+
+    /// Spawns the parts of a model, as children of the entity of the transform node of its shape.
+    ///
+    /// Returns the component marking the entity of the node if the model is split into chunks.
+    fn spawn_model(
+        &mut self,
+        builder: &mut WorldChildBuilder,
+        id: usize,
+        layer: Option<&VoxLayer>,
+        mirrored: bool,
+    ) -> Option<ChunkedShape> {
+        let spawned = self.spawned(id, mirrored)?;
+        let settings = self.settings;
+        let translation = self.pivot_translation(id);
+        let parts = spawned.chunks.as_ref().unwrap_or(&spawned.slots);
+
+        // opaque and transparent parts are spawned as separate entities so they're sorted independently.
+        for part in parts.iter() {
+            let mut entity = builder.spawn(part_bundle(
+                self.ctx.get_handle(&part.mesh),
+                self.ctx.get_handle(&self.materials[part.slot]),
+                translation,
+                part.name.clone(),
+            ));
+            if let Some(layer) = layer {
+                entity.insert(layer.clone());
+            }
+            if !part.lods.is_empty() {
+                entity.insert(VoxLod {
+                    meshes: std::iter::once(&part.mesh)
+                        .chain(&part.lods)
+                        .map(|mesh| self.ctx.get_handle(mesh))
+                        .collect(),
+                    distances: lod_distances(settings),
+                });
+            }
+        }
+
+        spawned.chunks.as_ref().map(|_| ChunkedShape {
+            voxels: self.ctx.get_handle(&spawned.voxels),
+            mirrored,
+            translation,
+        })
+    }
+
+    /// Spawns the models of a shape as an entity per material slot cycling through their sub-meshes.
+    ///
+    /// The models are shown from the frame index of their `_f` attribute, or their position in the shape, until the next one.
+    /// Models split into chunks are spawned whole, and without their less detailed meshes.
+    fn spawn_flipbook(
+        &mut self,
+        builder: &mut WorldChildBuilder,
+        shape_models: &[ShapeModel],
+        layer: Option<&VoxLayer>,
+        mirrored: bool,
+    ) {
+        let mut keyframes: Vec<(u32, usize)> = shape_models
+            .iter()
+            .enumerate()
+            .filter(|(_, model)| (model.model_id as usize) < self.meshes.len())
+            .map(|(i, model)| {
+                let frame = model
+                    .attributes
+                    .get(FRAME)
+                    .and_then(|frame| frame.parse().ok())
+                    .unwrap_or(i as u32);
+                (frame, model.model_id as usize)
+            })
+            .collect();
+        keyframes.sort_by_key(|(frame, _)| *frame);
+        let (Some((last_frame, _)), Some((_, first_model))) =
+            (keyframes.last().copied(), keyframes.first().copied())
+        else {
+            return;
+        };
+
+        let ids: Vec<usize> = (0..=last_frame)
+            .map(|frame| {
+                keyframes
+                    .iter()
+                    .rev()
+                    .find(|(keyframe, _)| *keyframe <= frame)
+                    .map_or(first_model, |(_, id)| *id)
+            })
+            .collect();
+        let frames: Vec<&SpawnedModel> = ids
+            .iter()
+            .map(|id| self.spawned(*id, mirrored).unwrap_or(&self.meshes[*id]))
+            .collect();
+        // each frame is placed on the pivot of its own model, as models of different sizes have different pivots.
+        let translations: Vec<Vec3> = ids.iter().map(|id| self.pivot_translation(*id)).collect();
+
+        for part in frames[0].slots.iter() {
+            let meshes: Vec<Handle<Mesh>> = frames
+                .iter()
+                .map(|spawned| self.ctx.get_handle(&spawned.slots[part.slot].mesh))
+                .collect();
+            let mut entity = builder.spawn((
+                PbrBundle {
+                    mesh: meshes[0].clone(),
+                    material: self.ctx.get_handle(&self.materials[part.slot]),
+                    transform: Transform::from_translation(translations[0]),
+                    ..PbrBundle::default()
+                },
+                VoxFlipbook::new(meshes, self.settings.frame_rate)
+                    .with_translations(translations.clone()),
+                Name::new(part.name.clone()),
+            ));
+            if let Some(layer) = layer {
+                entity.insert(layer.clone());
             }
         }
-        SceneNode::Shape {
+    }
+
+    /// Returns the translation of the meshes of a model from the entity of its transform node.
+    ///
+    /// MagicaVoxel places the center of a model at the origin of its node, while the mesh origin is at the pivot.
+    fn pivot_translation(&self, id: usize) -> Vec3 {
+        let Some(model) = self.models.get(id) else {
+            return Vec3::ZERO;
+        };
+        let size = [model.size.x, model.size.y, model.size.z];
+        let offset = self.settings.pivot.pivot(size) - PivotMode::Center.pivot(size);
+        self.settings.axis_convention.convert(offset) * self.settings.voxel_size
+    }
+
+    /// Returns the meshes spawned for a model, with reversed winding if the model is spawned mirrored.
+    fn spawned(&self, id: usize, mirrored: bool) -> Option<&'a SpawnedModel> {
+        let spawned = self.meshes.get(id)?;
+        match (mirrored, &spawned.mirrored) {
+            (true, Some(mirrored)) => Some(mirrored.as_ref()),
+            _ => Some(spawned),
+        }
+    }
+
+    /// Returns the name of a transform node, made unique among its siblings by appending the index of the node if needed,
+    /// given the names of its ancestors.
+    fn unique_name(&mut self, name: String, node: usize, path: &[Name]) -> String {
+        let node_path = |name: &str| {
+            let mut node_path = path.to_vec();
+            node_path.push(Name::new(name.to_owned()));
+            node_path
+        };
+        let name = match self.paths.contains(&node_path(&name)) {
+            true => format!("{name}#{node}"),
+            false => name,
+        };
+        self.paths.insert(node_path(&name));
+        name
+    }
+}
+
+/// Returns the keyframes of a transform node, with their time in seconds.
+///
+/// MagicaVoxel holds the transform of a keyframe until the next one, so each keyframe is followed by a copy of it
+/// just before the next one, and the last keyframe is held for a frame so that the animation loops evenly.
+fn animation_keyframes(frames: &[Dict], settings: &VoxLoaderSettings) -> Vec<(f32, Transform)> {
+    let mut frames: Vec<(u32, Transform)> = frames
+        .iter()
+        .map(|frame| {
+            let transform = frame_transform(frame, settings.axis_convention);
+            (frame_index(frame), transform)
+        })
+        .collect();
+    frames.sort_by_key(|(frame, _)| *frame);
+
+    let frame_duration = 1.0 / settings.frame_rate;
+    let mut keyframes = Vec::new();
+    for (i, (frame, transform)) in frames.iter().enumerate() {
+        let next = frames.get(i + 1).map_or(frame + 1, |(next, _)| *next);
+        keyframes.push((*frame as f32 * frame_duration, *transform));
+        keyframes.push(((next as f32 - 0.001) * frame_duration, *transform));
+    }
+    keyframes
+}
+
+/// Returns the frame index of a keyframe of a transform node.
+fn frame_index(frame: &Dict) -> u32 {
+    frame
+        .get(FRAME)
+        .and_then(|frame| frame.parse().ok())
+        .unwrap_or(0)
+}
+
+/// Returns the transform of a keyframe of a transform node, in voxel units.
+pub(crate) fn frame_transform(frame: &Dict, axis_convention: AxisConvention) -> Transform {
+    let (rotation, scale) =
+        extract_rotation(frame, axis_convention).unwrap_or((Quat::IDENTITY, Vec3::ONE));
+    Transform {
+        translation: extract_translation(frame, axis_convention).unwrap_or_default(),
+        rotation,
+        scale,
+    }
+}
+
+/// Checks that every shape node of the scene graph references an existing model.
+pub(crate) fn check_model_ids(scene: &[SceneNode], models: usize) -> Result<(), VoxLoadError> {
+    for (node, scene_node) in scene.iter().enumerate() {
+        if let SceneNode::Shape {
             models: shape_models,
             ..
-        } => {
-            for model in shape_models {
-                let id = model.model_id as usize;
-                if let (Some(mesh), Some(model)) = (meshes.get(id), models.get(id)) {
-                    // we swizzle z and y since bevy is y-up
-                    let size = UVec3::new(model.size.x, model.size.z, model.size.y).as_vec3();
-                    let mut pivot = (size / 2.0).floor();
-                    // we reverse x since MagicaVoxel's x axis is reversed
-                    pivot.x = -pivot.x;
-                    let translation = root_transform.transform_point(-pivot).floor();
-                    builder.spawn(PbrBundle {
-                        mesh: ctx.get_handle(mesh),
-                        material: ctx.get_handle(material),
-                        transform: Transform {
-                            translation,
-                            ..root_transform
-                        },
-                        ..PbrBundle::default()
+        } = scene_node
+        {
+            if let Some(model) = shape_models
+                .iter()
+                .find(|model| model.model_id as usize >= models)
+            {
+                return Err(VoxLoadError::InvalidModelId {
+                    node,
+                    model_id: model.model_id,
+                    models,
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that the `_r` attribute of every frame of the transform nodes of the scene graph is a packed rotation.
+pub(crate) fn check_rotations(scene: &[SceneNode]) -> Result<(), VoxLoadError> {
+    for (node, scene_node) in scene.iter().enumerate() {
+        let SceneNode::Transform { frames, .. } = scene_node else {
+            continue;
+        };
+        for frame in frames {
+            if let Some(rotation) = frame.get(ROTATION) {
+                if extract_rotation(frame, AxisConvention::ZUp).is_none() {
+                    return Err(VoxLoadError::InvalidRotation {
+                        node,
+                        rotation: rotation.clone(),
                     });
                 }
             }
         }
     }
+
+    Ok(())
 }
 
-pub(crate) fn extract_model_attributes(
-    model_id: usize,
-    scenes: &[SceneNode],
-) -> (Option<String>, Option<Vec3>) {
-    let model_id = model_id as u32;
+/// Returns the name, node index and model of the named instances, which are the named transform nodes of shapes.
+///
+/// Shapes with several models are instances of their first model.
+/// Names used by several nodes are suffixed with the index of the node after their first use.
+pub(crate) fn extract_instances(scene: &[SceneNode]) -> Vec<(String, usize, usize)> {
+    let mut instances = Vec::new();
+    let mut names = HashSet::default();
 
-    for (i, scene) in scenes.iter().enumerate() {
-        let child_id = i as u32;
+    for (node, scene_node) in scene.iter().enumerate() {
+        let SceneNode::Transform {
+            attributes, child, ..
+        } = scene_node
+        else {
+            continue;
+        };
+        let (Some(name), Some(SceneNode::Shape { models, .. })) =
+            (extract_name(attributes), scene.get(*child as usize))
+        else {
+            continue;
+        };
+        let Some(model) = models.first() else {
+            continue;
+        };
 
-        match scene {
-            dot_vox::SceneNode::Shape { models, .. } => {
-                if !models.iter().any(|model| model.model_id == model_id) {
-                    continue;
-                }
-            }
-            _ => continue,
+        let name = match names.contains(&name) {
+            true => format!("{name}-{node}"),
+            false => name,
         };
+        names.insert(name.clone());
+        instances.push((name, node, model.model_id as usize));
+    }
 
-        match scenes.iter().find_map(|scene| match scene {
-            SceneNode::Transform {
-                child,
-                attributes,
-                frames,
-            } => (*child == child_id).then_some((attributes, frames)),
-            _ => None,
-        }) {
-            Some((attributes, frames)) => {
-                return (extract_name(attributes), extract_translation(frames))
+    instances
+}
+
+/// Returns whether each model is spawned under a mirrored transform node, without considering animations.
+pub(crate) fn extract_mirrored(scene: &[SceneNode], models: usize) -> Vec<bool> {
+    fn visit(scene: &[SceneNode], node: usize, mirrored: bool, models: &mut [bool]) {
+        match scene.get(node) {
+            Some(SceneNode::Transform { frames, child, .. }) => {
+                // mirroring doesn't depend on the axes.
+                let transform = frames.first().map_or(Transform::IDENTITY, |frame| {
+                    frame_transform(frame, AxisConvention::YUp)
+                });
+                let scale = transform.scale;
+                visit(
+                    scene,
+                    *child as usize,
+                    mirrored != (scale.x * scale.y * scale.z < 0.0),
+                    models,
+                );
+            }
+            Some(SceneNode::Group { children, .. }) => {
+                for child in children {
+                    visit(scene, *child as usize, mirrored, models);
+                }
+            }
+            Some(SceneNode::Shape {
+                models: shape_models,
+                ..
+            }) if mirrored => {
+                for model in shape_models {
+                    if let Some(model) = models.get_mut(model.model_id as usize) {
+                        *model = true;
+                    }
+                }
             }
-            _ => continue,
+            _ => {}
         }
     }
 
-    (None, None)
+    let mut mirrored = vec![false; models];
+    visit(scene, 0, false, &mut mirrored);
+    mirrored
 }
 
 pub(crate) fn extract_name(attributes: &Dict) -> Option<String> {
@@ -145,56 +662,64 @@ pub(crate) fn extract_name(attributes: &Dict) -> Option<String> {
     }
 }
 
-pub(crate) fn extract_translation(frame: &[Dict]) -> Option<Vec3> {
-    frame
-        .get(0)
-        .and_then(|x| x.get(TRANSLATION))
-        .and_then(|translation| {
-            let mut components = translation.split(' ');
-            let x = components.next()?.parse::<f32>().ok()?;
-            let y = components.next()?.parse::<f32>().ok()?;
-            let z = components.next()?.parse::<f32>().ok()?;
-            if components.next() == None {
-                // we swizzle z and y since bevy is y-up
-                // we reverse x since MagicaVoxel's x axis is reversed
-                Some(Vec3::new(-x, z, y))
-            } else {
-                // there shouldn't be more than 3 components, bail
-                None
-            }
-        })
+pub(crate) fn extract_translation(frame: &Dict, axis_convention: AxisConvention) -> Option<Vec3> {
+    frame.get(TRANSLATION).and_then(|translation| {
+        let mut components = translation.split(' ');
+        let x = components.next()?.parse::<f32>().ok()?;
+        let y = components.next()?.parse::<f32>().ok()?;
+        let z = components.next()?.parse::<f32>().ok()?;
+        if components.next() == None {
+            Some(axis_convention.convert(Vec3::new(x, y, z)))
+        } else {
+            // there shouldn't be more than 3 components, bail
+            None
+        }
+    })
 }
 
+/// Returns the rotation of a keyframe, and the scale mirroring it if MagicaVoxel mirrors the node.
 // Based on https://github.com/jpaver/opengametools/blob/master/src/ogt_vox.h#L821
-pub(crate) fn extract_rotation(frame: &[Dict]) -> Option<Quat> {
-    frame
-        .get(0)
-        .and_then(|x| x.get(ROTATION))
-        .and_then(|translation| {
-            let packed = translation.parse::<u32>().ok()?;
-            let index0 = packed & 0b11;
-            let index1 = (packed >> 2u32) & 0b11;
-            let index2 = (1u32 << index0 | 1u32 << index1).trailing_ones();
-
-            #[inline(always)]
-            fn negate_if(x: u32) -> f32 {
-                if x == 0 {
-                    1.0
-                } else {
-                    -1.0
-                }
+pub(crate) fn extract_rotation(
+    frame: &Dict,
+    axis_convention: AxisConvention,
+) -> Option<(Quat, Vec3)> {
+    frame.get(ROTATION).and_then(|translation| {
+        let packed = translation.parse::<u32>().ok()?;
+        let index0 = packed & 0b11;
+        let index1 = (packed >> 2u32) & 0b11;
+        // the first two rows must have their non-zero entry in different columns.
+        if index0 > 2 || index1 > 2 || index0 == index1 {
+            return None;
+        }
+        let index2 = (1u32 << index0 | 1u32 << index1).trailing_ones();
+
+        #[inline(always)]
+        fn negate_if(x: u32) -> f32 {
+            if x == 0 {
+                1.0
+            } else {
+                -1.0
             }
+        }
 
-            let mut mat = Mat3::ZERO;
-            mat.x_axis[index0 as usize] = negate_if(packed & (1 << 4));
-            mat.y_axis[index1 as usize] = negate_if(packed & (1 << 5));
-            mat.z_axis[index2 as usize] = negate_if(packed & (1 << 6));
-
-            // we swizzle z and y since bevy is y-up
-            // we reverse every axis _except_ x to account for MagicaVoxel's x axis being reversed
-            let mut swizzled = Vec4::from(Quat::from_mat3(&mat)).xzyw();
-            swizzled.y = -swizzled.y;
-            swizzled.z = -swizzled.z;
-            Some(Quat::from_vec4(swizzled))
-        })
+        // the packed indices are the columns of the non-zero entry of each row.
+        let mut mat = Mat3::ZERO;
+        mat.x_axis[index0 as usize] = negate_if(packed & (1 << 4));
+        mat.y_axis[index1 as usize] = negate_if(packed & (1 << 5));
+        mat.z_axis[index2 as usize] = negate_if(packed & (1 << 6));
+        let mut mat = mat.transpose();
+
+        // a mirrored node has a reflection matrix, which isn't a rotation,
+        // so the reflection is split into a negative scale along MagicaVoxel's x axis applied before the rotation.
+        let mirrored = mat.determinant() < 0.0;
+        if mirrored {
+            mat.x_axis = -mat.x_axis;
+        }
+        let scale = match mirrored {
+            true => Vec3::ONE - 2.0 * axis_convention.convert(Vec3::X).abs(),
+            false => Vec3::ONE,
+        };
+
+        Some((Quat::from_mat3(&axis_convention.convert_matrix(mat)), scale))
+    })
 }
@@ -0,0 +1,329 @@
+use bevy::math::{Mat3, UVec3, Vec3};
+use block_mesh::{QuadCoordinateConfig, RIGHT_HANDED_Y_UP_CONFIG};
+use serde::Deserialize;
+
+/// The settings used when loading a `.vox` file.
+///
+/// The defaults for every file are set on the [`crate::VoxMeshPlugin`], and can be overridden for a single file
+/// by placing a [RON](https://github.com/ron-rs/ron) file next to it, with the same name and an additional `.meta` extension.
+/// Only the overridden settings need to be specified, for example `chicken.vox.meta` may contain:
+///
+/// ```ron
+/// (
+///     convert_rgb_to_linear: true,
+/// )
+/// ```
+#[derive(Clone, Debug)]
+pub struct VoxLoaderSettings {
+    /// Whether to flip the UVs vertically when meshing the models.
+    /// You may want to change this to false if you aren't using Vulkan as a graphical backend for bevy , else this should default to true.
+    pub v_flip_faces: bool,
+    /// Whether to convert the sRGB palette colors to linear RGB.
+    pub convert_rgb_to_linear: bool,
+    /// Whether palette entries without a material use the default material, instead of failing with [`crate::VoxLoadError::InconsistentMaterials`].
+    pub tolerant_materials: bool,
+    /// The size of a voxel in world units, applied to the generated meshes and to the translations of the scene.
+    /// It must be positive, else loading fails with [`crate::VoxLoadError::Settings`].
+    pub voxel_size: f32,
+    /// Where the origin of the generated meshes is placed.
+    pub pivot: PivotMode,
+    /// Whether to bake per-vertex ambient occlusion into the vertex colors of the generated meshes.
+    /// This has no effect on meshes generated with [`MeshingAlgorithm::SurfaceNets`].
+    ///
+    /// Quads are only merged with neighbours sharing the same occlusion, so this produces more vertices.
+    pub ambient_occlusion: bool,
+    /// The algorithm used to turn the voxels into meshes.
+    pub meshing: MeshingAlgorithm,
+    /// How the palette colors are applied to the generated meshes.
+    pub color_mode: ColorMode,
+    /// The size in voxels of the chunks the models are split into, `None` to spawn each model as a whole.
+    ///
+    /// The meshes of each chunk are generated next to the meshes of the whole model, and the spawned scene uses them instead,
+    /// so that the chunks of large models can be frustum-culled individually.
+    /// Every chunk is spawned with an entity per material slot used by its voxels, and edits spawn the ones they fill.
+    /// Models meshed with [`MeshingAlgorithm::SurfaceNets`] are never split into chunks.
+    /// In a `.vox.meta` file, a chunk size of `0` disables the splitting.
+    pub chunk_size: Option<u32>,
+    /// The number of less detailed meshes generated for each model, each one halving the resolution of the previous one.
+    ///
+    /// The spawned scene swaps the meshes of models with a [`crate::VoxLod`], unless they're split into chunks.
+    pub lod_levels: u32,
+    /// The distance to the nearest camera from which the first less detailed mesh is used, doubling with each level.
+    pub lod_distance: f32,
+    /// The collision geometry generated for each model, `None` to skip generating it.
+    pub collider: Option<ColliderMode>,
+    /// The number of animation frames per second, used to time the keyframes of the scene.
+    /// It must be positive, else loading fails with [`crate::VoxLoadError::Settings`].
+    pub frame_rate: f32,
+    /// What happens to the shapes of the layers hidden in MagicaVoxel when the scene is spawned.
+    pub hidden_layers: HiddenLayers,
+    /// The axes of the generated meshes, voxel grids and scenes.
+    pub axis_convention: AxisConvention,
+}
+
+impl Default for VoxLoaderSettings {
+    fn default() -> Self {
+        Self {
+            v_flip_faces: true,
+            convert_rgb_to_linear: false,
+            tolerant_materials: false,
+            voxel_size: 1.0,
+            pivot: PivotMode::Center,
+            ambient_occlusion: false,
+            meshing: MeshingAlgorithm::Greedy,
+            color_mode: ColorMode::VertexColors,
+            chunk_size: None,
+            lod_levels: 0,
+            lod_distance: 50.0,
+            collider: None,
+            frame_rate: 10.0,
+            hidden_layers: HiddenLayers::Hide,
+            axis_convention: AxisConvention::YUp,
+        }
+    }
+}
+
+impl VoxLoaderSettings {
+    /// Checks that the settings can be used to load a file, returning a description of the first invalid one.
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if !(self.voxel_size.is_finite() && self.voxel_size > 0.0) {
+            return Err(format!(
+                "voxel_size must be a positive number, got {}",
+                self.voxel_size
+            ));
+        }
+        if !(self.frame_rate.is_finite() && self.frame_rate > 0.0) {
+            return Err(format!(
+                "frame_rate must be a positive number, got {}",
+                self.frame_rate
+            ));
+        }
+        if self.chunk_size == Some(0) {
+            return Err(
+                "chunk_size must be positive, or None to disable the splitting".to_string(),
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The origin of the meshes generated for the models of a `.vox` file.
+///
+/// The spawned scene takes the pivot into account, so that the origin of each spawned model is its pivot.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub enum PivotMode {
+    /// The center of the model, as used by MagicaVoxel, rounded down to a whole voxel.
+    Center,
+    /// The center of the bottom face of the model, rounded down to a whole voxel, so that the model stands on its origin.
+    BottomCenter,
+    /// The corner of the model with the lowest MagicaVoxel coordinates.
+    MinCorner,
+    /// An explicit position, in voxels, in the MagicaVoxel coordinates of the model.
+    Voxel([f32; 3]),
+}
+
+impl PivotMode {
+    /// Returns the pivot of a model with the given MagicaVoxel size, in MagicaVoxel coordinates.
+    pub(crate) fn pivot(&self, size: [u32; 3]) -> Vec3 {
+        let center = (UVec3::from(size).as_vec3() / 2.0).floor();
+        match self {
+            PivotMode::Center => center,
+            // MagicaVoxel is z-up
+            PivotMode::BottomCenter => Vec3::new(center.x, center.y, 0.0),
+            PivotMode::MinCorner => Vec3::ZERO,
+            PivotMode::Voxel(pivot) => Vec3::from(*pivot),
+        }
+    }
+}
+
+/// The algorithm used to turn voxels into meshes.
+///
+/// Every algorithm produces meshes with the same vertex attributes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub enum MeshingAlgorithm {
+    /// Merges adjacent faces of the same color into bigger quads, producing the fewest vertices.
+    Greedy,
+    /// Generates a quad for every visible voxel face, which keeps per-voxel effects such as ambient occlusion accurate.
+    Culled,
+    /// Generates a smooth surface enclosing the voxels, colored after the nearest voxels.
+    SurfaceNets,
+}
+
+/// How the palette colors are applied to the generated meshes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub enum ColorMode {
+    /// The colors are stored in the vertex colors of the meshes, and the UVs map the voxel faces.
+    VertexColors,
+    /// The UVs point into the `#palette` texture, which is the base color texture of the materials.
+    /// The vertex colors are left white, except for the ambient occlusion.
+    PaletteTexture,
+}
+
+/// The collision geometry generated for the models, as a [`crate::VoxCollider`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub enum ColliderMode {
+    /// Boxes greedily merged from the solid voxels, producing few boxes.
+    Boxes,
+    /// Boxes a single voxel high, greedily merged from the solid voxels of each horizontal layer.
+    LayerBoxes,
+    /// The triangles of the `#model{no}` mesh.
+    TriMesh,
+}
+
+/// What happens to the shapes of the layers hidden in MagicaVoxel, such as reference meshes or collision proxies.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub enum HiddenLayers {
+    /// The shapes are spawned with their [`bevy::render::view::Visibility`] off, so that they can still be queried.
+    Hide,
+    /// The shapes aren't spawned.
+    Skip,
+    /// The shapes are spawned visible, as if the layers weren't hidden.
+    Show,
+}
+
+/// How MagicaVoxel's axes are converted into the axes of the generated meshes, voxel grids and scenes.
+///
+/// MagicaVoxel is right-handed and z-up. Right-handed conventions only differ from it by a rotation,
+/// while left-handed conventions mirror it, and wind the triangles of the meshes the other way around so that they face out
+/// in left-handed axes. Bevy is right-handed, so meshes generated for left-handed conventions are meant to be exported,
+/// or rendered under a transform with a negative scale.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub enum AxisConvention {
+    /// Bevy's axes, where y is up.
+    YUp,
+    /// MagicaVoxel's own axes: z is up, as used by z-up physics engines.
+    ZUp,
+    /// Left-handed axes where y is up, as used by Unity.
+    YUpLeftHanded,
+    /// Left-handed axes where z is up, as used by Unreal Engine.
+    ZUpLeftHanded,
+}
+
+impl AxisConvention {
+    /// Returns the MagicaVoxel axis along each axis of the convention, and whether it is reversed.
+    pub(crate) fn axes(self) -> [(usize, bool); 3] {
+        match self {
+            // MagicaVoxel's x axis is reversed once y and z are swapped, so that the axes stay right-handed.
+            AxisConvention::YUp => [(0, true), (2, false), (1, false)],
+            AxisConvention::ZUp => [(0, false), (1, false), (2, false)],
+            AxisConvention::YUpLeftHanded => [(0, false), (2, false), (1, false)],
+            AxisConvention::ZUpLeftHanded => [(0, false), (1, true), (2, false)],
+        }
+    }
+
+    /// Whether the axes are left-handed, mirroring MagicaVoxel's axes.
+    pub(crate) fn is_left_handed(self) -> bool {
+        matches!(
+            self,
+            AxisConvention::YUpLeftHanded | AxisConvention::ZUpLeftHanded
+        )
+    }
+
+    /// Converts a position or a direction from MagicaVoxel's axes.
+    pub(crate) fn convert(self, vec: Vec3) -> Vec3 {
+        Vec3::from(
+            self.axes()
+                .map(|(axis, reversed)| if reversed { -vec[axis] } else { vec[axis] }),
+        )
+    }
+
+    /// Converts a matrix acting on MagicaVoxel's axes.
+    pub(crate) fn convert_matrix(self, matrix: Mat3) -> Mat3 {
+        let axes = Mat3::from_cols(
+            self.convert(Vec3::X),
+            self.convert(Vec3::Y),
+            self.convert(Vec3::Z),
+        );
+        axes * matrix * axes.transpose()
+    }
+
+    /// Returns the quad coordinates configuration used to mesh the voxels.
+    ///
+    /// The voxels are always meshed in MagicaVoxel's axes in (x, z, y) order, which are then converted into the axes of the convention,
+    /// so the configuration is the same for every convention.
+    pub(crate) fn quads_config(self) -> QuadCoordinateConfig {
+        RIGHT_HANDED_Y_UP_CONFIG
+    }
+
+    /// Returns the index of the up axis.
+    pub(crate) fn up(self) -> usize {
+        match self {
+            AxisConvention::YUp | AxisConvention::YUpLeftHanded => 1,
+            AxisConvention::ZUp | AxisConvention::ZUpLeftHanded => 2,
+        }
+    }
+
+    /// Returns the size of a voxel grid, given the size of the model in MagicaVoxel's axes.
+    pub(crate) fn grid_size(self, size: [u32; 3]) -> UVec3 {
+        UVec3::from(self.axes().map(|(axis, _)| size[axis]))
+    }
+
+    /// Returns the grid position of a voxel, given its position and the size of the model in MagicaVoxel's axes.
+    pub(crate) fn grid_position(self, position: [u32; 3], size: [u32; 3]) -> UVec3 {
+        UVec3::from(self.axes().map(|(axis, reversed)| match reversed {
+            true => size[axis] - 1 - position[axis],
+            false => position[axis],
+        }))
+    }
+
+    /// Returns the position of a voxel in MagicaVoxel's axes, given its grid position and the size of the grid.
+    pub(crate) fn magica_position(self, position: UVec3, size: UVec3) -> [u32; 3] {
+        let mut magica = [0; 3];
+        for (i, (axis, reversed)) in self.axes().into_iter().enumerate() {
+            magica[axis] = match reversed {
+                true => size[i] - 1 - position[i],
+                false => position[i],
+            };
+        }
+        magica
+    }
+}
+
+/// The settings found in a `.vox.meta` file, applied on top of the plugin defaults.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct VoxLoaderSettingsOverrides {
+    v_flip_faces: Option<bool>,
+    convert_rgb_to_linear: Option<bool>,
+    tolerant_materials: Option<bool>,
+    voxel_size: Option<f32>,
+    pivot: Option<PivotMode>,
+    ambient_occlusion: Option<bool>,
+    meshing: Option<MeshingAlgorithm>,
+    color_mode: Option<ColorMode>,
+    chunk_size: Option<u32>,
+    lod_levels: Option<u32>,
+    lod_distance: Option<f32>,
+    collider: Option<ColliderMode>,
+    frame_rate: Option<f32>,
+    hidden_layers: Option<HiddenLayers>,
+    axis_convention: Option<AxisConvention>,
+}
+
+impl VoxLoaderSettingsOverrides {
+    pub(crate) fn apply(self, settings: &VoxLoaderSettings) -> VoxLoaderSettings {
+        VoxLoaderSettings {
+            v_flip_faces: self.v_flip_faces.unwrap_or(settings.v_flip_faces),
+            convert_rgb_to_linear: self
+                .convert_rgb_to_linear
+                .unwrap_or(settings.convert_rgb_to_linear),
+            tolerant_materials: self
+                .tolerant_materials
+                .unwrap_or(settings.tolerant_materials),
+            voxel_size: self.voxel_size.unwrap_or(settings.voxel_size),
+            pivot: self.pivot.unwrap_or(settings.pivot),
+            ambient_occlusion: self.ambient_occlusion.unwrap_or(settings.ambient_occlusion),
+            meshing: self.meshing.unwrap_or(settings.meshing),
+            color_mode: self.color_mode.unwrap_or(settings.color_mode),
+            chunk_size: self.chunk_size.map_or(settings.chunk_size, |chunk_size| {
+                (chunk_size > 0).then_some(chunk_size)
+            }),
+            lod_levels: self.lod_levels.unwrap_or(settings.lod_levels),
+            lod_distance: self.lod_distance.unwrap_or(settings.lod_distance),
+            collider: self.collider.or(settings.collider),
+            frame_rate: self.frame_rate.unwrap_or(settings.frame_rate),
+            hidden_layers: self.hidden_layers.unwrap_or(settings.hidden_layers),
+            axis_convention: self.axis_convention.unwrap_or(settings.axis_convention),
+        }
+    }
+}
@@ -0,0 +1,2 @@
+mod axes;
+mod errors;
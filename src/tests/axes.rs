@@ -0,0 +1,253 @@
+//! Checks that the axis conventions only change the axes of the voxel grids, meshes and scenes, and nothing else.
+
+use std::sync::Arc;
+
+use bevy::{
+    math::{Ray, UVec3, Vec3},
+    prelude::{GlobalTransform, Handle, Mesh},
+    render::mesh::{MeshVertexAttributeId, VertexAttributeValues},
+};
+use dot_vox::{Dict, Model, Size, Voxel};
+
+use crate::{
+    material::PaletteMaterials, mesh::ModelMesher, scene::frame_transform, AxisConvention,
+    PivotMode, VoxLoaderSettings, VoxModel,
+};
+
+/// A model without any symmetry, as `(x, y, z, palette index)` in MagicaVoxel's axes.
+const VOXELS: [(u8, u8, u8, u8); 4] = [(0, 0, 0, 1), (2, 0, 0, 2), (0, 1, 0, 3), (0, 0, 3, 4)];
+const SIZE: [u32; 3] = [3, 2, 4];
+
+const AXIS_CONVENTIONS: [AxisConvention; 4] = [
+    AxisConvention::YUp,
+    AxisConvention::ZUp,
+    AxisConvention::YUpLeftHanded,
+    AxisConvention::ZUpLeftHanded,
+];
+
+/// Converts a vector from the z-up convention, which has MagicaVoxel's axes, into another convention.
+fn from_z_up(axis_convention: AxisConvention, vec: Vec3) -> Vec3 {
+    match axis_convention {
+        AxisConvention::YUp => Vec3::new(-vec.x, vec.z, vec.y),
+        AxisConvention::ZUp => vec,
+        AxisConvention::YUpLeftHanded => Vec3::new(vec.x, vec.z, vec.y),
+        AxisConvention::ZUpLeftHanded => Vec3::new(vec.x, -vec.y, vec.z),
+    }
+}
+
+fn settings(axis_convention: AxisConvention) -> VoxLoaderSettings {
+    VoxLoaderSettings {
+        voxel_size: 0.5,
+        pivot: PivotMode::Center,
+        axis_convention,
+        ..VoxLoaderSettings::default()
+    }
+}
+
+fn load_model(settings: &VoxLoaderSettings) -> (VoxModel, Mesh) {
+    let model = Model {
+        size: Size {
+            x: SIZE[0],
+            y: SIZE[1],
+            z: SIZE[2],
+        },
+        voxels: VOXELS
+            .iter()
+            .map(|&(x, y, z, i)| Voxel { x, y, z, i })
+            .collect(),
+    };
+    let origin = crate::mesh::mesh_origin(SIZE, settings);
+    let voxels = VoxModel::from_model(
+        &model,
+        origin,
+        settings.voxel_size,
+        Handle::default(),
+        settings.axis_convention,
+    );
+
+    let mut palette = vec![[1.0; 4]; 256];
+    let materials = PaletteMaterials::new(&mut palette, &[], true).unwrap();
+    let mesh = ModelMesher::new(
+        &voxels,
+        palette.into(),
+        Arc::new(materials),
+        Arc::new(settings.axis_convention.quads_config()),
+        settings,
+    )
+    .mesh_loaded(&voxels)
+    .mesh;
+    (voxels, mesh)
+}
+
+fn vec3_attribute(mesh: &Mesh, attribute: impl Into<MeshVertexAttributeId>) -> Vec<Vec3> {
+    match mesh.attribute(attribute) {
+        Some(VertexAttributeValues::Float32x3(values)) => {
+            values.iter().map(|value| Vec3::from(*value)).collect()
+        }
+        _ => panic!("missing attribute"),
+    }
+}
+
+/// Returns the center of a voxel in the space of the meshes of the z-up convention.
+fn magica_center(x: u8, y: u8, z: u8, settings: &VoxLoaderSettings) -> Vec3 {
+    let pivot = settings.pivot.pivot(SIZE);
+    (Vec3::new(x as f32, y as f32, z as f32) + 0.5 - pivot) * settings.voxel_size
+}
+
+fn assert_close(a: Vec3, b: Vec3) {
+    assert!(a.abs_diff_eq(b, 1e-4), "{a} != {b}");
+}
+
+#[test]
+fn voxel_grids_agree() {
+    let z_up = settings(AxisConvention::ZUp);
+    let (z_model, _) = load_model(&z_up);
+    assert_eq!(z_model.size(), UVec3::new(3, 2, 4));
+
+    for axis_convention in AXIS_CONVENTIONS {
+        let convert = |vec: Vec3| from_z_up(axis_convention, vec);
+        let (model, _) = load_model(&settings(axis_convention));
+
+        assert_eq!(
+            model.size().as_vec3(),
+            convert(z_model.size().as_vec3()).abs()
+        );
+        assert_close(model.origin(), {
+            // the lowest corner of the grid is made of the lowest coordinates of opposite z-up corners.
+            let min = z_model.origin();
+            let max = min + z_model.size().as_vec3() * z_up.voxel_size;
+            convert(min).min(convert(max))
+        });
+
+        for (x, y, z, index) in VOXELS {
+            let center = convert(magica_center(x, y, z, &z_up));
+            let position = model.voxel_at(center).unwrap();
+            assert_eq!(model.get(position.x, position.y, position.z), Some(index));
+            assert_close(model.voxel_center(position), center);
+        }
+        assert_eq!(model.iter().count(), VOXELS.len());
+    }
+}
+
+#[test]
+fn meshes_agree() {
+    let (_, z_mesh) = load_model(&settings(AxisConvention::ZUp));
+    let z_positions = vec3_attribute(&z_mesh, Mesh::ATTRIBUTE_POSITION);
+    let z_normals = vec3_attribute(&z_mesh, Mesh::ATTRIBUTE_NORMAL);
+
+    for axis_convention in AXIS_CONVENTIONS {
+        let (model, mesh) = load_model(&settings(axis_convention));
+
+        // the voxels are meshed in the same order whatever the convention, only the axes of the vertices change.
+        let positions = vec3_attribute(&mesh, Mesh::ATTRIBUTE_POSITION);
+        let normals = vec3_attribute(&mesh, Mesh::ATTRIBUTE_NORMAL);
+        assert_eq!(positions.len(), z_positions.len());
+        for (position, z_position) in positions.iter().zip(&z_positions) {
+            assert_close(*position, from_z_up(axis_convention, *z_position));
+        }
+        for (normal, z_normal) in normals.iter().zip(&z_normals) {
+            assert_close(*normal, from_z_up(axis_convention, *z_normal));
+        }
+
+        // the meshes span the grid of their model.
+        let min = positions
+            .iter()
+            .fold(Vec3::splat(f32::INFINITY), |a, b| a.min(*b));
+        let max = positions
+            .iter()
+            .fold(Vec3::splat(f32::NEG_INFINITY), |a, b| a.max(*b));
+        assert_close(min, model.origin());
+        assert_close(
+            max,
+            model.origin() + model.size().as_vec3() * model.voxel_size(),
+        );
+    }
+}
+
+/// Returns the dot product of the normal of each triangle, following its winding, with the normal of its first vertex.
+fn winding(mesh: &Mesh) -> Vec<f32> {
+    let positions = vec3_attribute(mesh, Mesh::ATTRIBUTE_POSITION);
+    let normals = vec3_attribute(mesh, Mesh::ATTRIBUTE_NORMAL);
+    let indices: Vec<usize> = mesh.indices().unwrap().iter().collect();
+    indices
+        .chunks_exact(3)
+        .map(|triangle| {
+            let [a, b, c] = [0, 1, 2].map(|i| positions[triangle[i]]);
+            (b - a).cross(c - a).dot(normals[triangle[0]])
+        })
+        .collect()
+}
+
+#[test]
+fn winding_follows_handedness() {
+    for axis_convention in [AxisConvention::YUp, AxisConvention::ZUp] {
+        let (_, mesh) = load_model(&settings(axis_convention));
+        assert!(winding(&mesh).iter().all(|dot| *dot > 0.0));
+    }
+    // left-handed front faces are counter-clockwise in left-handed axes, so clockwise when read as right-handed.
+    for axis_convention in [AxisConvention::YUpLeftHanded, AxisConvention::ZUpLeftHanded] {
+        let (_, mesh) = load_model(&settings(axis_convention));
+        assert!(winding(&mesh).iter().all(|dot| *dot < 0.0));
+    }
+}
+
+#[test]
+fn raycasts_agree() {
+    let z_up = settings(AxisConvention::ZUp);
+    let (z_model, _) = load_model(&z_up);
+
+    // a ray going down the column of the voxels at (0, 0, 0) and (0, 0, 3) hits the top one.
+    let top = magica_center(0, 0, 3, &z_up);
+    let z_ray = Ray {
+        origin: top + Vec3::Z * 10.0,
+        direction: -Vec3::Z,
+    };
+    let z_hit = z_model
+        .raycast(&GlobalTransform::IDENTITY, z_ray, 100.0)
+        .unwrap();
+    assert_eq!(z_hit.index, 4);
+
+    for axis_convention in AXIS_CONVENTIONS {
+        let convert = |vec: Vec3| from_z_up(axis_convention, vec);
+        let (model, _) = load_model(&settings(axis_convention));
+        let ray = Ray {
+            origin: convert(z_ray.origin),
+            direction: convert(z_ray.direction),
+        };
+        let hit = model
+            .raycast(&GlobalTransform::IDENTITY, ray, 100.0)
+            .unwrap();
+
+        assert_eq!(hit.index, 4);
+        assert_close(hit.point, convert(z_hit.point));
+        assert!((hit.distance - z_hit.distance).abs() < 1e-4);
+        assert_close(model.voxel_center(hit.position), convert(top));
+    }
+}
+
+#[test]
+fn scene_transforms_agree() {
+    // identity, a quarter turn around MagicaVoxel's z axis, and a mirror along its x axis.
+    for rotation in ["4", "17", "20"] {
+        let frame: Dict = [
+            ("_t".to_string(), "1 -2 3".to_string()),
+            ("_r".to_string(), rotation.to_string()),
+        ]
+        .into_iter()
+        .collect();
+        let z_transform = frame_transform(&frame, AxisConvention::ZUp);
+
+        for axis_convention in AXIS_CONVENTIONS {
+            let convert = |vec: Vec3| from_z_up(axis_convention, vec);
+            let transform = frame_transform(&frame, axis_convention);
+
+            assert_close(transform.translation, convert(z_transform.translation));
+            for point in [Vec3::X, Vec3::Y, Vec3::Z, Vec3::new(1.0, 2.0, 3.0)] {
+                assert_close(
+                    transform.transform_point(convert(point)),
+                    convert(z_transform.transform_point(point)),
+                );
+            }
+        }
+    }
+}
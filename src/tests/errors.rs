@@ -0,0 +1,90 @@
+//! Checks that malformed `.vox` files fail to load with an error instead of panicking.
+
+use dot_vox::{Dict, Model, SceneNode, Size, Voxel};
+
+use crate::{
+    model::check_models,
+    scene::{check_rotations, frame_transform},
+    AxisConvention, VoxLoadError,
+};
+
+fn model(size: [u32; 3], voxels: &[(u8, u8, u8)]) -> Model {
+    Model {
+        size: Size {
+            x: size[0],
+            y: size[1],
+            z: size[2],
+        },
+        voxels: voxels
+            .iter()
+            .map(|&(x, y, z)| Voxel { x, y, z, i: 0 })
+            .collect(),
+    }
+}
+
+fn transform_node(rotation: &str) -> SceneNode {
+    let frame: Dict = [("_r".to_string(), rotation.to_string())]
+        .into_iter()
+        .collect();
+    SceneNode::Transform {
+        attributes: Dict::default(),
+        frames: vec![frame],
+        child: 1,
+    }
+}
+
+#[test]
+fn voxels_outside_of_the_model_are_rejected() {
+    let models = [
+        model([2, 2, 2], &[(0, 0, 0), (1, 1, 1)]),
+        model([2, 3, 2], &[(0, 2, 0), (0, 0, 2)]),
+    ];
+    assert!(check_models(&models[..1]).is_ok());
+    assert!(matches!(
+        check_models(&models),
+        Err(VoxLoadError::VoxelOutOfBounds {
+            model: 1,
+            position: [0, 0, 2],
+            size: [2, 3, 2],
+        })
+    ));
+}
+
+#[test]
+fn oversized_models_are_rejected() {
+    assert!(matches!(
+        check_models(&[model([1, 300, 1], &[])]),
+        Err(VoxLoadError::ModelTooLarge {
+            model: 0,
+            size: [1, 300, 1],
+        })
+    ));
+}
+
+#[test]
+fn invalid_rotations_are_rejected() {
+    // the identity, then a first row pointing at a fourth column, two rows with the same column, and a rotation which isn't a number.
+    let scene = [transform_node("4")];
+    assert!(check_rotations(&scene).is_ok());
+    for rotation in ["3", "5", "x"] {
+        let scene = [transform_node("4"), transform_node(rotation)];
+        match check_rotations(&scene) {
+            Err(VoxLoadError::InvalidRotation {
+                node: 1,
+                rotation: invalid,
+            }) => assert_eq!(invalid, rotation),
+            result => panic!("expected an invalid rotation, got {result:?}"),
+        }
+    }
+}
+
+#[test]
+fn invalid_rotations_are_ignored_by_transforms() {
+    let frame: Dict = [("_r".to_string(), "15".to_string())].into_iter().collect();
+    for axis_convention in [AxisConvention::YUp, AxisConvention::ZUp] {
+        assert_eq!(
+            frame_transform(&frame, axis_convention),
+            bevy::prelude::Transform::IDENTITY
+        );
+    }
+}
@@ -1,20 +1,18 @@
-use block_mesh::{MergeVoxel, Voxel as BlockyVoxel};
-use dot_vox::Model;
+use block_mesh::{MergeVoxel, Voxel as BlockyVoxel, VoxelVisibility};
 use ndshape::RuntimeShape;
 use ndshape::Shape;
 
+use crate::{material::PaletteMaterials, VoxModel};
+
 // trait implementation rules requires the use of a newtype to allow meshing.
 #[derive(Clone, Copy, PartialEq, Eq)]
-pub(crate) struct Voxel(pub(crate) u8);
+pub(crate) struct Voxel(pub(crate) u8, pub(crate) VoxelVisibility);
 
-pub(crate) const EMPTY_VOXEL: Voxel = Voxel(255);
+pub(crate) const EMPTY_VOXEL: Voxel = Voxel(255, VoxelVisibility::Empty);
 
 impl BlockyVoxel for Voxel {
-    fn get_visibility(&self) -> block_mesh::VoxelVisibility {
-        match self.0 {
-            255 => block_mesh::VoxelVisibility::Empty,
-            _ => block_mesh::VoxelVisibility::Opaque,
-        }
+    fn get_visibility(&self) -> VoxelVisibility {
+        self.1
     }
 }
 
@@ -26,17 +24,32 @@ impl MergeVoxel for Voxel {
     }
 }
 
-pub(crate) fn load_from_model(model: &Model) -> (RuntimeShape<u32, 3>, Vec<Voxel>) {
-    let model_shape =
-        RuntimeShape::<u32, 3>::new([model.size.x + 2, model.size.z + 2, model.size.y + 2]);
-    let mut data = vec![EMPTY_VOXEL; model_shape.size() as usize];
-
-    model.voxels.iter().for_each(|voxel| {
-        let index =
-            model_shape.linearize([voxel.x as u32 + 1, voxel.z as u32 + 1, voxel.y as u32 + 1])
-                as usize;
-        data[index] = Voxel(voxel.i);
-    });
-
-    (model_shape, data)
+/// Copies a region of the voxels of a model into a buffer padded by one voxel on each side, as required for meshing.
+///
+/// The region and the buffer axes are MagicaVoxel's (x, z, y) axes, which the meshing converts into the axes of the meshes.
+/// The padding contains the neighbours of the region, so that faces between regions are culled.
+pub(crate) fn load_region(
+    model: &VoxModel,
+    materials: &PaletteMaterials,
+    min: [u32; 3],
+    size: [u32; 3],
+) -> (RuntimeShape<u32, 3>, Vec<Voxel>) {
+    let region_shape = RuntimeShape::<u32, 3>::new(size.map(|x| x + 2));
+
+    let data = (0..region_shape.size())
+        .map(|index| {
+            let position = region_shape.delinearize(index);
+            let [x, y, z] = [0, 1, 2].map(|axis| (min[axis] + position[axis]).checked_sub(1));
+            let palette_index = match (x, y, z) {
+                (Some(x), Some(y), Some(z)) => model.get_magica([x, z, y]),
+                _ => None,
+            };
+            // translucent voxels don't hide the faces of their opaque neighbours.
+            palette_index.map_or(EMPTY_VOXEL, |index| {
+                Voxel(index, materials.visibility(index))
+            })
+        })
+        .collect();
+
+    (region_shape, data)
 }